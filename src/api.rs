@@ -1,25 +1,77 @@
+use crate::builder::RequestBuilder;
+use crate::error::BaichuanError;
+use crate::metrics::RequestMetrics;
+use crate::transport::{Transport, TransportResponse};
+use crate::wire;
 use chrono::{self};
 use clap::ValueEnum;
-use log::debug;
 use md5::compute;
-use reqwest::{self, StatusCode};
+use reqwest::{self, header::HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_repr::Deserialize_repr;
-use std::collections::HashMap;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use tracing::{debug, info_span, warn, Instrument};
 use ulid::Ulid;
 
 fn md5_hash(s: &str) -> String {
     format!("{:x}", compute(s))
 }
 
+fn sha256_hash(s: &str) -> String {
+    format!("{:x}", Sha256::digest(s))
+}
+
+/// Digest used for the `X-BC-Signature` header.
+#[derive(PartialEq, ValueEnum, Clone, Copy, Default, Debug)]
+pub enum SignAlgo {
+    #[default]
+    Md5,
+    Sha256,
+}
+
+impl SignAlgo {
+    fn hash(&self, s: &str) -> String {
+        match self {
+            SignAlgo::Md5 => md5_hash(s),
+            SignAlgo::Sha256 => sha256_hash(s),
+        }
+    }
+
+    /// Value of the `X-BC-Sign-Algo` header.
+    fn header_value(&self) -> &'static str {
+        match self {
+            SignAlgo::Md5 => "MD5",
+            SignAlgo::Sha256 => "SHA256",
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
-struct BaichuanReq {
+pub(crate) struct BaichuanReq {
     model: Model,
     messages: Vec<ChatMessage>,
     parameters: Parameters,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A tool the model may draw on while answering.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Tool {
+    /// Looks up passages from knowledge bases uploaded to the platform.
+    Retrieval { retrieval: Retrieval },
+}
+
+#[derive(Serialize, Debug)]
+struct Retrieval {
+    kb_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
@@ -27,6 +79,53 @@ pub struct ChatMessage {
     pub finish_reason: Option<String>,
 }
 
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        ChatMessage {
+            role: "user".into(),
+            content: content.into(),
+            finish_reason: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        ChatMessage {
+            role: "assistant".into(),
+            content: content.into(),
+            finish_reason: None,
+        }
+    }
+
+    /// Instructions steering the assistant, sent ahead of the conversation.
+    /// Baichuan documents the `system` role for its OpenAI compatible API
+    /// (<https://platform.baichuan-ai.com/docs/api>), while the docs of this
+    /// chat API list `user` and `assistant` only, so it may ignore it.
+    pub fn system(content: impl Into<String>) -> Self {
+        ChatMessage {
+            role: "system".into(),
+            content: content.into(),
+            finish_reason: None,
+        }
+    }
+
+    /// Whether the answer was cut off by the length limit rather than
+    /// finished by the model.
+    pub fn was_truncated(&self) -> bool {
+        matches!(self.finish_reason.as_deref(), Some("length" | "max_tokens"))
+    }
+
+    /// Marks an answer the user stopped while it was streamed in, so that
+    /// it is known to be incomplete when the conversation is saved. The API
+    /// never gives this `finish_reason` itself.
+    pub fn cancel(&mut self) {
+        self.finish_reason = Some("cancelled".into());
+    }
+
+    pub fn was_cancelled(&self) -> bool {
+        self.finish_reason.as_deref() == Some("cancelled")
+    }
+}
+
 // 成功	成功	0	success	请求成功并获得预期的结果
 // 请求错误	失败	1	system error	请求失败
 // 请求错误	参数非法	10000	Invalid parameters, please check	请求中的参数不合法，请仔细检查
@@ -46,7 +145,7 @@ pub struct ChatMessage {
 // 安全错误	prompt 不安全	10400	Topic violates security policy	返回的 prompt 内容不符合安全策略
 // 安全错误	answer 不安全	10401	Topic violates security policy	返回的 answer 内容不符合安全策略
 // 服务错误	服务内部错误	10500	Internal error	服务内部发生错误，请稍后再试
-#[derive(Deserialize_repr, PartialEq, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, PartialEq, Eq, Clone, Copy, Debug)]
 #[repr(i32)]
 pub enum RespCode {
     Success = 0,
@@ -70,19 +169,89 @@ pub enum RespCode {
     InternalError = 10500,
 }
 
-#[derive(Deserialize, Debug)]
+impl RespCode {
+    /// Whether the prompt or the answer was rejected by the content filter.
+    pub fn is_safety_violation(&self) -> bool {
+        matches!(self, RespCode::PromptNotSafe | RespCode::AnswerNotSafe)
+    }
+
+    /// What the user can do about the error, for showing next to the message.
+    pub fn user_hint(&self) -> &'static str {
+        match self {
+            RespCode::Success => "No action needed.",
+            RespCode::SystemError => "The request failed on the server, try again.",
+            RespCode::InvalidParameters => {
+                "Check the model name and parameters such as temperature and top_p."
+            }
+            RespCode::MissingApikey => {
+                "Pass your api key with --api-key, the config file or API_KEY."
+            }
+            RespCode::InvalidApikey => {
+                "Check that the api key was copied completely from https://platform.baichuan-ai.com."
+            }
+            RespCode::ApikeyExpired => {
+                "Create a new api key at https://platform.baichuan-ai.com."
+            }
+            RespCode::InvalidTimestamp => "The request timestamp is malformed, please report this.",
+            RespCode::ExpireTimestamp => "Make sure your system clock is set correctly.",
+            RespCode::InvalidSignature => {
+                "Check the secret key, and try --sign-algo md5 if you changed it."
+            }
+            RespCode::InvalidEncryptionAlgorithm => "Use --sign-algo md5.",
+            RespCode::AccountNotFound => "Check that the api key belongs to an existing account.",
+            RespCode::AccountLocked => "Contact Baichuan support to unlock your account.",
+            RespCode::AccountTempLocked => "Your account is temporarily locked, try again later.",
+            RespCode::AccountRequestTooFrequent => {
+                "Slow down, each api key is limited to 10 requests per minute."
+            }
+            RespCode::AccountBalanceInsufficient => {
+                "Top up your account balance at https://platform.baichuan-ai.com."
+            }
+            RespCode::AccountNotVerified => {
+                "Complete the account verification at https://platform.baichuan-ai.com."
+            }
+            RespCode::PromptNotSafe => "Rephrase the prompt to comply with the content policy.",
+            RespCode::AnswerNotSafe => "Rephrase the prompt, the answer was withheld by the content policy.",
+            RespCode::InternalError => "Baichuan had an internal error, try again later.",
+        }
+    }
+}
+
+/// Shown in place of the token usage of a response that didn't report it,
+/// rather than counting it as zero tokens.
+pub const USAGE_UNAVAILABLE: &str = "(usage unavailable)";
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
 pub struct UsageInfo {
     pub prompt_tokens: i64,
     pub answer_tokens: i64,
     pub total_tokens: i64,
 }
 
-#[derive(Deserialize, Debug)]
+impl std::ops::AddAssign<&UsageInfo> for UsageInfo {
+    fn add_assign(&mut self, other: &UsageInfo) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.answer_tokens += other.answer_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+impl std::fmt::Display for UsageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "prompt={} answer={} total={}",
+            self.prompt_tokens, self.answer_tokens, self.total_tokens
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BaichuanData {
     pub messages: Vec<ChatMessage>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BaichuanResp {
     pub code: RespCode,
     pub msg: String,
@@ -92,84 +261,794 @@ pub struct BaichuanResp {
     pub usage: Option<UsageInfo>,
 }
 
-#[derive(Serialize, Debug)]
-struct Parameters(HashMap<String, String>);
+impl BaichuanResp {
+    /// The text of the assistant messages, joined together.
+    pub fn answer(&self) -> String {
+        self.data
+            .iter()
+            .flat_map(|data| &data.messages)
+            .filter(|m| m.role == "assistant")
+            .map(|m| m.content.as_str())
+            .collect()
+    }
+
+    /// The [`answer`](Self::answer) parsed as JSON, e.g. in reply to a
+    /// request with [`ResponseFormat::JsonObject`]. A markdown code block
+    /// around the JSON is ignored, as models tend to add one.
+    pub fn answer_json<T: DeserializeOwned>(&self) -> Result<T, BaichuanError> {
+        let answer = self.answer();
+        let json = answer.trim();
+        let json = json
+            .strip_prefix("```json")
+            .or_else(|| json.strip_prefix("```"))
+            .and_then(|json| json.strip_suffix("```"))
+            .unwrap_or(json);
+        serde_json::from_str(json).map_err(|source| BaichuanError::AnswerNotJson { source, answer })
+    }
+
+    /// Whether a successful response came back without any answer text,
+    /// either lacking `data` altogether or with no non-empty message.
+    pub fn is_empty(&self) -> bool {
+        self.data
+            .as_ref()
+            .is_none_or(|data| data.messages.iter().all(|m| m.content.is_empty()))
+    }
+}
 
+/// The messages as `[role]: content` lines followed by the token usage, the
+/// way `bc-cli` prints them, or the error of an unsuccessful response. Use
+/// `{:?}` for every field.
+impl std::fmt::Display for BaichuanResp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.code != RespCode::Success {
+            return write!(f, "{:?}: {}", self.code, self.msg);
+        }
+        for message in self.data.iter().flat_map(|data| &data.messages) {
+            writeln!(f, "[{}]: {}", message.role, message.content)?;
+        }
+        match &self.usage {
+            Some(usage) => write!(f, "[usage] {}", usage),
+            None => write!(f, "[usage] {}", USAGE_UNAVAILABLE),
+        }
+    }
+}
+
+/// What form the answer takes.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// A JSON object. The prompt should still ask for JSON and describe it,
+    /// as the model isn't told otherwise what the object holds.
+    JsonObject,
+}
+
+/// Sampling parameters, only the fields that are set are sent.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Parameters {
+    /// Randomness of the answer, between 0.0 and 1.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling probability mass, between 0.0 and 1.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Number of highest probability tokens considered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+    /// Penalty on tokens already generated, between 1.0 for none and 2.0.
+    /// The open Baichuan2 models default to 1.05. Not documented for the
+    /// chat API, which may ignore it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f32>,
+    /// Enrich the answer with web search results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub with_search_enhance: Option<bool>,
+    /// Upper bound on the tokens generated for the answer. An answer that
+    /// hits it ends with `finish_reason` `"length"`, see
+    /// [`ChatMessage::was_truncated`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// End the answer before the first of these texts would be generated.
+    /// Not documented for the chat API, which may ignore it, nor is a limit
+    /// on the number of sequences.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Ask for the same answer to the same request. Baichuan doesn't
+    /// document seeding and may ignore it, and even where it is honored a
+    /// non-zero temperature can still vary the answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Number of alternative answers to generate, each returned as one of
+    /// [`BaichuanData::messages`]. Not documented by Baichuan, which may
+    /// return a single answer regardless.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Ask for the answer in this form. Baichuan documents it for its
+    /// OpenAI compatible API only, so this endpoint may ignore it; see
+    /// [`BaichuanResp::answer_json`] for checking the answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Knowledge base to answer from. It is sent as a retrieval tool next to
+    /// the parameters rather than as one of them.
+    #[serde(skip_serializing)]
+    pub knowledge_base_id: Option<String>,
+    /// Parameters without a field of their own, e.g. ones added to the API
+    /// after this crate was released, sent next to the others. Where a key
+    /// is also set as one of the fields above, the field wins.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Parameters {
+    pub fn validate(&self) -> Result<(), BaichuanError> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=1.0).contains(&temperature) {
+                return Err(BaichuanError::InvalidParameter(format!(
+                    "temperature must be between 0.0 and 1.0, got {}",
+                    temperature
+                )));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                return Err(BaichuanError::InvalidParameter(format!(
+                    "top_p must be between 0.0 and 1.0, got {}",
+                    top_p
+                )));
+            }
+        }
+        if let Some(repetition_penalty) = self.repetition_penalty {
+            if !(1.0..=2.0).contains(&repetition_penalty) {
+                return Err(BaichuanError::InvalidParameter(format!(
+                    "repetition_penalty must be between 1.0 and 2.0, got {}",
+                    repetition_penalty
+                )));
+            }
+        }
+        if self.knowledge_base_id.as_deref() == Some("") {
+            return Err(BaichuanError::InvalidParameter(
+                "knowledge_base_id must not be empty".to_string(),
+            ));
+        }
+        if self.n == Some(0) {
+            return Err(BaichuanError::InvalidParameter(
+                "n must be at least 1".to_string(),
+            ));
+        }
+        if self.max_tokens == Some(0) {
+            return Err(BaichuanError::InvalidParameter(
+                "max_tokens must be at least 1".to_string(),
+            ));
+        }
+        if self.stop.iter().flatten().any(String::is_empty) {
+            return Err(BaichuanError::InvalidParameter(
+                "stop sequences must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Rejects obviously unusable keys locally, before any request is sent.
+/// Baichuan doesn't document a fixed key format, so only empty keys and keys
+/// with whitespace or control characters (which can't be sent in a header)
+/// are caught here.
+pub fn validate_credentials(api_key: &str, secret_key: &str) -> Result<(), BaichuanError> {
+    for (name, key) in [("api key", api_key), ("secret key", secret_key)] {
+        if key.trim().is_empty() {
+            return Err(BaichuanError::InvalidCredentials(format!(
+                "{} is empty",
+                name
+            )));
+        }
+        if key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(BaichuanError::InvalidCredentials(format!(
+                "{} contains whitespace or control characters",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The `X-BC-Signature` of `body` sent at `timestamp`, in seconds.
+fn signature(secret_key: &str, body: &str, timestamp: i64, sign_algo: SignAlgo) -> String {
+    sign_algo.hash(&format!("{}{}{}", secret_key, body, timestamp))
+}
+
+/// The `X-BC-Timestamp` of a request sent at `now`: whole seconds since the
+/// Unix epoch, truncated. That is what the API documents, and what its
+/// samples send with `int(time.time())`. Milliseconds would be taken for a
+/// time far in the future and the request rejected with
+/// [`RespCode::InvalidTimestamp`], looking like a bad signature.
+fn request_timestamp(now: chrono::DateTime<chrono::Utc>) -> i64 {
+    now.timestamp()
+}
+
+/// A fresh `X-BC-Request-Id`.
+pub(crate) fn new_request_id() -> String {
+    Ulid::new().to_string()
+}
+
+/// The headers for sending `data` at `timestamp`, in seconds, together with
+/// the request id, which is generated unless one is given.
 fn generate_header(
-    api_key: &String,
-    secret_key: &String,
-    data: &BaichuanReq,
-) -> Result<(HashMap<String, String>, String), String> {
-    // current timestamp in seconds
-    let timestamp = chrono::Utc::now().timestamp();
-    let serialized_request = serde_json::to_string(&data).map_err(|e| e.to_string())?;
-    let signature = md5_hash(&format!(
-        "{}{}{}",
-        secret_key, serialized_request, timestamp,
-    ));
-    let request_id = Ulid::new();
+    api_key: &str,
+    secret_key: &str,
+    data: &impl Serialize,
+    sign_algo: SignAlgo,
+    timestamp: i64,
+    request_id: Option<&str>,
+) -> Result<(HashMap<String, String>, String), BaichuanError> {
+    let serialized_request = serde_json::to_string(&data).map_err(BaichuanError::Serialization)?;
+    let signature = signature(secret_key, &serialized_request, timestamp, sign_algo);
+    let request_id = request_id.map_or_else(new_request_id, str::to_string);
     let headers = HashMap::from([
         ("Content-Type".to_string(), "application/json".to_string()),
         ("Authorization".to_string(), format!("Bearer {}", &api_key)),
-        ("X-BC-Request-Id".to_string(), request_id.to_string()),
+        ("X-BC-Request-Id".to_string(), request_id.clone()),
         ("X-BC-Timestamp".to_string(), timestamp.to_string()),
         ("X-BC-Signature".to_string(), signature),
-        ("X-BC-Sign-Algo".to_string(), "MD5".to_string()),
+        (
+            "X-BC-Sign-Algo".to_string(),
+            sign_algo.header_value().to_string(),
+        ),
     ]);
-    Ok((headers, request_id.to_string()))
+    Ok((headers, request_id))
 }
 
-#[derive(Serialize, PartialEq, ValueEnum, Clone, Copy, Debug)]
+/// Models served by the chat endpoint. The CLI accepts the same names as the
+/// API, as well as their lower case forms.
+#[derive(Serialize, Deserialize, PartialEq, ValueEnum, Clone, Copy, Default, Debug)]
 pub enum Model {
+    #[default]
     #[serde(rename = "Baichuan2-53B")]
+    #[value(name = "Baichuan2-53B", alias = "baichuan2-53b")]
     Baichuan2_53B,
+    #[serde(rename = "Baichuan2-13B")]
+    #[value(name = "Baichuan2-13B", alias = "baichuan2-13b")]
+    Baichuan2_13B,
+    #[serde(rename = "Baichuan2-Turbo")]
+    #[value(name = "Baichuan2-Turbo", alias = "baichuan2-turbo")]
+    Baichuan2Turbo,
+    #[serde(rename = "Baichuan2-Turbo-192k")]
+    #[value(name = "Baichuan2-Turbo-192k", alias = "baichuan2-turbo-192k")]
+    Baichuan2Turbo192k,
+}
+
+impl std::fmt::Display for Model {
+    /// The name the API knows the model by, e.g. `Baichuan2-53B`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Model::Baichuan2_53B => "Baichuan2-53B",
+            Model::Baichuan2_13B => "Baichuan2-13B",
+            Model::Baichuan2Turbo => "Baichuan2-Turbo",
+            Model::Baichuan2Turbo192k => "Baichuan2-Turbo-192k",
+        };
+        f.write_str(name)
+    }
 }
 
-const URL: &str = "https://api.baichuan-ai.com/v1/chat";
+pub const DEFAULT_BASE_URL: &str = "https://api.baichuan-ai.com";
+/// Sent with every request unless [`HttpOptions::user_agent`] says otherwise,
+/// so the API provider can tell which client and version the traffic is from.
+pub const DEFAULT_USER_AGENT: &str = concat!("baichuan_cli/", env!("CARGO_PKG_VERSION"));
+pub(crate) const CHAT_PATH: &str = "/v1/chat";
 
-pub async fn make_baichuan_request(
-    api_key: &String,
-    secret_key: &String,
+/// Joins an endpoint path onto a base URL such as [`DEFAULT_BASE_URL`].
+pub(crate) fn endpoint(base_url: &str, path: &str) -> String {
+    format!("{}{}", base_url.trim_end_matches('/'), path)
+}
+
+/// Checks that `base_url` is an absolute `http(s)` URL, warning when it is
+/// not HTTPS since the signed requests carry the api key.
+pub fn validate_base_url(base_url: &str) -> Result<(), BaichuanError> {
+    let url = reqwest::Url::parse(base_url)
+        .map_err(|e| BaichuanError::Config(format!("invalid base url {:?}: {}", base_url, e)))?;
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" => {
+            warn!("base url {} is not using HTTPS", base_url);
+            Ok(())
+        }
+        scheme => Err(BaichuanError::Config(format!(
+            "invalid base url {:?}: unsupported scheme {}",
+            base_url, scheme
+        ))),
+    }
+}
+
+/// Settings for the HTTP client built by [`http_client`].
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    /// Bounds each request from connecting until the response body has been
+    /// read, streamed answers included. `None` for no bound, e.g. to stream
+    /// long answers while relying on `read_timeout` to catch stalls.
+    pub timeout: Option<Duration>,
+    /// Bounds establishing the connection, so an unreachable server is
+    /// given up on quickly.
+    pub connect_timeout: Duration,
+    /// Bounds the wait for each read of the response, however long the
+    /// whole response takes. Not supported by the blocking client.
+    pub read_timeout: Option<Duration>,
+    /// `http://`, `https://` or `socks5://` proxy used for all requests. When
+    /// unset, the `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` environment variables
+    /// are honored.
+    pub proxy: Option<String>,
+    /// `User-Agent` header of every request
+    pub user_agent: String,
+    /// Accept any TLS certificate, even an expired or self-signed one or one
+    /// for another host, as for a gateway intercepting TLS with its own
+    /// certificate. Anyone on the network path can then read the keys and
+    /// the conversation, so it is off by default.
+    pub danger_accept_invalid_certs: bool,
+    /// Keep idle connections open, probing them with TCP keepalives at this
+    /// interval, so that a request after a pause needn't connect afresh.
+    /// Nothing is sent at the HTTP level, so no requests are charged or
+    /// counted against the rate limit. When unset, connections idle for 90
+    /// seconds are closed.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            timeout: Some(Duration::from_secs(120)),
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: None,
+            proxy: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            danger_accept_invalid_certs: false,
+            keepalive: None,
+        }
+    }
+}
+
+impl HttpOptions {
+    pub(crate) fn proxy(&self) -> Result<Option<reqwest::Proxy>, BaichuanError> {
+        self.proxy
+            .as_deref()
+            .map(|url| {
+                reqwest::Proxy::all(url)
+                    .map_err(|e| BaichuanError::Config(format!("invalid proxy {:?}: {}", url, e)))
+            })
+            .transpose()
+    }
+}
+
+/// Builds the HTTP client shared by all requests, so connections are pooled
+/// across calls.
+pub fn http_client(options: &HttpOptions) -> Result<reqwest::Client, BaichuanError> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(options.connect_timeout)
+        .user_agent(&options.user_agent)
+        .danger_accept_invalid_certs(options.danger_accept_invalid_certs);
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(read_timeout) = options.read_timeout {
+        builder = builder.read_timeout(read_timeout);
+    }
+    if let Some(interval) = options.keepalive {
+        builder = builder.tcp_keepalive(interval).pool_idle_timeout(None);
+    }
+    if let Some(proxy) = options.proxy()? {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+pub(crate) fn build_request(
     model: Model,
-    messages: Vec<String>,
-) -> Result<BaichuanResp, String> {
-    let request = BaichuanReq {
+    messages: Vec<ChatMessage>,
+    parameters: &Parameters,
+) -> Result<BaichuanReq, BaichuanError> {
+    parameters.validate()?;
+    let tools = parameters
+        .knowledge_base_id
+        .iter()
+        .map(|id| Tool::Retrieval {
+            retrieval: Retrieval {
+                kb_ids: vec![id.clone()],
+            },
+        })
+        .collect();
+    let mut parameters = parameters.clone();
+    if !parameters.extra.is_empty() {
+        let typed = serde_json::to_value(Parameters {
+            extra: BTreeMap::new(),
+            ..parameters.clone()
+        })
+        .map_err(BaichuanError::Serialization)?;
+        parameters.extra.retain(|key, _| typed.get(key).is_none());
+    }
+    Ok(BaichuanReq {
         model,
-        messages: messages
-            .into_iter()
-            .map(|m| ChatMessage {
-                role: "user".into(),
-                content: m,
-                finish_reason: None,
-            })
+        messages,
+        parameters,
+        tools,
+    })
+}
+
+pub(crate) fn user_messages(messages: Vec<String>) -> Vec<ChatMessage> {
+    messages.into_iter().map(ChatMessage::user).collect()
+}
+
+/// Prepends the system prompt, if any, to the conversation as a
+/// [`ChatMessage::system`] message, which the chat API may ignore.
+pub(crate) fn with_system_prompt(
+    system_prompt: Option<&str>,
+    messages: Vec<ChatMessage>,
+) -> Vec<ChatMessage> {
+    match system_prompt {
+        Some(prompt) => std::iter::once(ChatMessage::system(prompt))
+            .chain(messages)
             .collect(),
-        parameters: Parameters(HashMap::default()),
-    };
-    let (headers, req_id) = generate_header(api_key, secret_key, &request)?;
-    let client = reqwest::Client::new();
-    let headers = (&headers).try_into().expect("failed to convert to error");
-    debug!("starting request {}", req_id);
+        None => messages,
+    }
+}
+
+/// A request with its signed headers, exactly as it is posted. Its `Debug`
+/// output has the api key and the signature redacted.
+#[derive(Clone)]
+pub struct SignedRequest {
+    pub url: String,
+    pub headers: HeaderMap,
+    /// The JSON body the signature was computed over.
+    pub body: String,
+    pub request_id: String,
+}
+
+impl std::fmt::Debug for SignedRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Headers<'a>(&'a HeaderMap);
+        impl std::fmt::Debug for Headers<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_map()
+                    .entries(
+                        self.0.iter().map(|(name, value)| {
+                            (name, wire::header_value(name, value, false, true))
+                        }),
+                    )
+                    .finish()
+            }
+        }
+        f.debug_struct("SignedRequest")
+            .field("url", &self.url)
+            .field("headers", &Headers(&self.headers))
+            .field("body", &self.body)
+            .field("request_id", &self.request_id)
+            .finish()
+    }
+}
+
+impl SignedRequest {
+    /// A `curl` command posting the request, with the api key redacted
+    /// unless `show_secrets`. The server only accepts it until the signed
+    /// timestamp expires.
+    pub fn to_curl(&self, show_secrets: bool) -> String {
+        let mut command = format!("curl -X POST {}", shell_quote(&self.url));
+        for header in wire::format_headers(&self.headers, show_secrets).lines() {
+            command.push_str(&format!(" \\\n  -H {}", shell_quote(header)));
+        }
+        command.push_str(&format!(" \\\n  --data {}", shell_quote(&self.body)));
+        command
+    }
+}
+
+/// Quotes `text` as a single POSIX shell word.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Serializes and signs `request` for posting to `url`. The timestamp is
+/// taken now, so every attempt of a retried request is signed afresh rather
+/// than reusing a signature that may have expired in the meantime.
+///
+/// The attempts of a retried request share `request_id` though, so that
+/// they can be told apart from separate requests in the server's logs. The
+/// API doesn't document deduplicating requests by their id, so a retry may
+/// still be answered, and charged, as a request of its own.
+pub(crate) fn sign_request(
+    url: String,
+    api_key: &str,
+    secret_key: &str,
+    sign_algo: SignAlgo,
+    request: &impl Serialize,
+    request_id: Option<&str>,
+) -> Result<SignedRequest, BaichuanError> {
+    let timestamp = request_timestamp(chrono::Utc::now());
+    let (headers, request_id) = generate_header(
+        api_key, secret_key, request, sign_algo, timestamp, request_id,
+    )?;
+    let headers =
+        HeaderMap::try_from(&headers).map_err(|e| BaichuanError::Signature(e.to_string()))?;
+    let body = serde_json::to_string(request).map_err(BaichuanError::Serialization)?;
+    Ok(SignedRequest {
+        url,
+        headers,
+        body,
+        request_id,
+    })
+}
+
+/// The request id the server echoed in its response headers, falling back to
+/// the one we sent.
+pub(crate) fn response_request_id(headers: &HeaderMap, sent: String) -> String {
+    headers
+        .get("X-BC-Request-Id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or(sent)
+}
+
+/// A rejected timestamp is blamed on the local clock when it is this far off
+/// the server's.
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
+
+/// How far the local clock at `now` is ahead of the server's `Date` header.
+pub(crate) fn clock_skew(
+    headers: &HeaderMap,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<chrono::Duration> {
+    let date = headers.get(reqwest::header::DATE)?.to_str().ok()?;
+    let server_time = chrono::DateTime::parse_from_rfc2822(date).ok()?;
+    Some(now.signed_duration_since(server_time))
+}
+
+/// Warns on [`RespCode::ExpireTimestamp`] if the local clock is off, since
+/// re-sending the request won't help then.
+pub(crate) fn warn_on_clock_skew(err: &BaichuanError, headers: &HeaderMap) {
+    if err.resp_code() != Some(RespCode::ExpireTimestamp) {
+        return;
+    }
+    if let Some(skew) = clock_skew(headers, chrono::Utc::now()) {
+        warn_if_skewed(skew);
+    }
+}
+
+/// Warns if the local clock is far enough off the server's for the request
+/// timestamps to be rejected.
+pub(crate) fn warn_if_skewed(skew: chrono::Duration) {
+    if skew.num_seconds().abs() > MAX_CLOCK_SKEW_SECS {
+        warn!(
+            "the local clock is {}s {} the server's, which invalidates request timestamps",
+            skew.num_seconds().abs(),
+            if skew.num_seconds() > 0 {
+                "ahead of"
+            } else {
+                "behind"
+            }
+        );
+    }
+}
+
+/// How long [`server_clock_skew`] waits for the server.
+const CLOCK_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How far the local clock is ahead of the server at `base_url`, going by
+/// the `Date` header of a `HEAD` request. `None` if it sent no date.
+pub(crate) async fn server_clock_skew(
+    client: &reqwest::Client,
+    base_url: &str,
+) -> Result<Option<chrono::Duration>, BaichuanError> {
     let response = client
-        .post(URL)
-        .headers(headers)
-        .json(&request)
+        .head(base_url)
+        .timeout(CLOCK_CHECK_TIMEOUT)
         .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    if response.status() == StatusCode::OK {
-        debug!("request {} was successful", req_id);
-        match response.json().await {
-            Ok(resp) => Ok(resp),
-            Err(e) => Err(format!("failed to parse json: {}", e)),
+        .await?;
+    Ok(clock_skew(response.headers(), chrono::Utc::now()))
+}
+
+/// Signs `request` and posts it to `url`, returning the response and its
+/// request id only if the server answered with `200 OK`.
+pub(crate) async fn post_request(
+    client: &reqwest::Client,
+    url: String,
+    api_key: &str,
+    secret_key: &str,
+    sign_algo: SignAlgo,
+    request: &BaichuanReq,
+    request_id: Option<&str>,
+) -> Result<(reqwest::Response, String), BaichuanError> {
+    let signed = sign_request(url, api_key, secret_key, sign_algo, request, request_id)?;
+    let span = info_span!("request", id = %signed.request_id, model = %request.model);
+    async move {
+        let started = Instant::now();
+        debug!("starting request");
+        wire::log_request(&signed.url, &signed.headers, signed.body.as_bytes());
+        let response = client
+            .post(signed.url)
+            .headers(signed.headers)
+            .body(signed.body)
+            .send()
+            .await?;
+        let status = response.status();
+        wire::log_response(status, response.headers());
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        if status == StatusCode::OK {
+            let req_id = response_request_id(response.headers(), signed.request_id);
+            debug!(elapsed_ms, server_id = %req_id, "response started");
+            Ok((response, req_id))
+        } else {
+            debug!(elapsed_ms, %status, "request failed");
+            let headers = response.headers().clone();
+            let body = response.bytes().await?;
+            wire::log_body(&body);
+            Err(BaichuanError::status(status, &headers, &body))
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// Records how a request ended on its span.
+fn log_outcome(result: &Result<(BaichuanResp, RequestMetrics), BaichuanError>, started: Instant) {
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok((resp, metrics)) => debug!(
+            elapsed_ms,
+            request_bytes = metrics.request_bytes,
+            response_bytes = metrics.response_bytes,
+            code = ?resp.code,
+            total_tokens = resp.usage.as_ref().map(|u| u.total_tokens),
+            "request was successful"
+        ),
+        Err(e) => debug!(elapsed_ms, code = ?e.resp_code(), "request failed: {}", e),
+    }
+}
+
+/// Sends a request to the chat endpoint, signed by [`sign_request`], and
+/// parses the answer.
+pub(crate) async fn send_request(
+    transport: &impl Transport,
+    signed: SignedRequest,
+    model: Model,
+    timeout: Option<Duration>,
+) -> Result<(BaichuanResp, RequestMetrics), BaichuanError> {
+    let span = info_span!("request", id = %signed.request_id, model = %model);
+    async move {
+        let started = Instant::now();
+        debug!("starting request");
+        let request_bytes = signed.body.len();
+        let result = async {
+            let TransportResponse { headers, body } = transport
+                .post_json(
+                    &signed.url,
+                    signed.headers,
+                    signed.body.into_bytes(),
+                    timeout,
+                )
+                .await?;
+            let metrics = RequestMetrics {
+                request_bytes,
+                response_bytes: body.len(),
+                latency: started.elapsed(),
+            };
+            let req_id = response_request_id(&headers, signed.request_id);
+            parse_resp(&body, Some(&req_id))
+                .inspect_err(|e| warn_on_clock_skew(e, &headers))
+                .map(|resp| (resp, metrics))
         }
+        .await;
+        log_outcome(&result, started);
+        result
+    }
+    .instrument(span)
+    .await
+}
+
+/// Parses a chat response body, rejecting non-success codes.
+pub(crate) fn parse_resp(
+    body: &[u8],
+    request_id: Option<&str>,
+) -> Result<BaichuanResp, BaichuanError> {
+    let resp = serde_json::from_slice(body).map_err(|e| BaichuanError::parse(e, body))?;
+    check_resp(resp, request_id)
+}
+
+/// Turns a response with a non-success code into [`BaichuanError::Api`].
+pub(crate) fn check_resp(
+    resp: BaichuanResp,
+    request_id: Option<&str>,
+) -> Result<BaichuanResp, BaichuanError> {
+    if resp.code == RespCode::Success {
+        Ok(resp)
     } else {
-        Err(format!(
-            "failed to send request: {:?}",
-            response.text().await
-        ))
+        Err(BaichuanError::Api {
+            code: resp.code,
+            msg: resp.msg,
+            request_id: request_id.map(str::to_string),
+        })
     }
 }
 
+pub async fn make_baichuan_request(
+    client: &impl Transport,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<String>,
+    parameters: &Parameters,
+) -> Result<BaichuanResp, BaichuanError> {
+    make_baichuan_request_with_history(
+        client,
+        api_key,
+        secret_key,
+        model,
+        user_messages(messages),
+        parameters,
+    )
+    .await
+}
+
+/// Sends a pre-built conversation, e.g. previous `user` and `assistant`
+/// turns followed by the new prompt.
+pub async fn make_baichuan_request_with_history(
+    client: &impl Transport,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<ChatMessage>,
+    parameters: &Parameters,
+) -> Result<BaichuanResp, BaichuanError> {
+    RequestBuilder::new(client, api_key, secret_key)
+        .model(model)
+        .messages(messages)
+        .parameters(parameters.clone())
+        .send()
+        .await
+}
+
+/// Sends `prompt` on its own and returns just the answer text. It builds a
+/// new HTTP client each time, so keep a [`crate::BaichuanClient`] to send
+/// more than a few prompts.
+pub async fn ask(
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    prompt: &str,
+) -> Result<String, BaichuanError> {
+    let resp = ask_with(api_key, secret_key, model, prompt, &Parameters::default()).await?;
+    Ok(resp.answer())
+}
+
+/// Like [`ask`], but asks for a JSON answer and parses it into a `T`. An
+/// answer that isn't such JSON is a [`BaichuanError::AnswerNotJson`].
+pub async fn ask_json<T: DeserializeOwned>(
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    prompt: &str,
+) -> Result<T, BaichuanError> {
+    let parameters = Parameters {
+        response_format: Some(ResponseFormat::JsonObject),
+        ..Parameters::default()
+    };
+    ask_with(api_key, secret_key, model, prompt, &parameters)
+        .await?
+        .answer_json()
+}
+
+async fn ask_with(
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    prompt: &str,
+    parameters: &Parameters,
+) -> Result<BaichuanResp, BaichuanError> {
+    let http = http_client(&HttpOptions::default())?;
+    make_baichuan_request(
+        &http,
+        api_key,
+        secret_key,
+        model,
+        vec![prompt.to_string()],
+        parameters,
+    )
+    .await
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -180,24 +1059,248 @@ mod test {
     }
 
     #[test]
-    fn test_gen_header() -> Result<(), String> {
-        let api_key = &"api key".to_string();
-        let secret_key = &"secret key".to_string();
+    fn test_to_curl() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer key".parse().unwrap());
+        headers.insert("X-BC-Timestamp", "1700000000".parse().unwrap());
+        let request = SignedRequest {
+            url: "https://api.baichuan-ai.com/v1/chat".to_string(),
+            headers,
+            body: r#"{"content":"it's"}"#.to_string(),
+            request_id: "id".to_string(),
+        };
+        assert_eq!(
+            request.to_curl(false),
+            "curl -X POST 'https://api.baichuan-ai.com/v1/chat' \\\n  \
+             -H 'authorization: Bearer ********' \\\n  \
+             -H 'x-bc-timestamp: 1700000000' \\\n  \
+             --data '{\"content\":\"it'\\''s\"}'"
+        );
+        assert!(request
+            .to_curl(true)
+            .contains("-H 'authorization: Bearer key'"));
+    }
+
+    #[test]
+    fn test_signed_request_debug_is_redacted() -> Result<(), BaichuanError> {
+        let request = sign_request(
+            endpoint(DEFAULT_BASE_URL, CHAT_PATH),
+            "sk-api-key",
+            "secret-key",
+            SignAlgo::Md5,
+            &serde_json::json!({"messages": []}),
+            None,
+        )?;
+        let debug = format!("{:?}", request);
+        assert!(
+            debug.contains(r#""authorization": "Bearer ********""#),
+            "{}",
+            debug
+        );
+        assert!(
+            debug.contains(r#""x-bc-signature": "********""#),
+            "{}",
+            debug
+        );
+        let signature = request.headers["X-BC-Signature"].to_str().unwrap();
+        for secret in ["sk-api-key", "secret-key", signature] {
+            assert!(!debug.contains(secret), "{}", debug);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gen_header() -> Result<(), BaichuanError> {
+        let api_key = "api key";
+        let secret_key = "secret key";
         let data = BaichuanReq {
             model: Model::Baichuan2_53B,
-            messages: vec![ChatMessage {
-                role: "user".to_string(),
-                content: "1".to_string(),
-                finish_reason: None,
-            }],
-            parameters: Parameters(HashMap::default()),
-        };
-        let (generated_header, _) = generate_header(api_key, secret_key, &data)?;
+            messages: vec![ChatMessage::user("1")],
+            parameters: Parameters::default(),
+            tools: vec![],
+        };
+        let (generated_header, _) = generate_header(
+            api_key,
+            secret_key,
+            &data,
+            SignAlgo::Md5,
+            1_700_000_000,
+            None,
+        )?;
         let content_type = generated_header.get("Content-Type");
         assert_eq!(Some(&"application/json".to_string()), content_type);
+        assert_eq!(
+            Some(&"MD5".to_string()),
+            generated_header.get("X-BC-Sign-Algo")
+        );
+
+        assert_eq!(generated_header["X-BC-Timestamp"], "1700000000");
+        assert_eq!(
+            generated_header["X-BC-Signature"],
+            "d06fd8c313bcecce61d6f8510b618f7b"
+        );
+
+        let (generated_header, _) = generate_header(
+            api_key,
+            secret_key,
+            &data,
+            SignAlgo::Sha256,
+            1_700_000_000,
+            None,
+        )?;
+        assert_eq!(
+            Some(&"SHA256".to_string()),
+            generated_header.get("X-BC-Sign-Algo")
+        );
+        assert_eq!(
+            generated_header["X-BC-Signature"],
+            "3c64813a19d66483e01ba5ca17aa2960896ba0fe96e27015771dc9643b8d40a4"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_vector() {
+        assert_eq!(
+            signature("secret", "{}", 1_700_000_000, SignAlgo::Md5),
+            "3956d6903f191ebcee8702845266bcef"
+        );
+        assert_ne!(
+            signature("secret", "{}", 1_700_000_000, SignAlgo::Md5),
+            signature("secret", "{}", 1_700_000_001, SignAlgo::Md5)
+        );
+    }
+
+    #[test]
+    fn test_request_timestamp_is_in_seconds() -> Result<(), BaichuanError> {
+        let now = chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, 1_700_000_000_999).unwrap();
+        assert_eq!(request_timestamp(now), 1_700_000_000);
+        let before = chrono::Utc::now().timestamp();
+        let signed = sign_request(
+            endpoint(DEFAULT_BASE_URL, CHAT_PATH),
+            "api key",
+            "secret key",
+            SignAlgo::Md5,
+            &serde_json::json!({}),
+            None,
+        )?;
+        let header = signed.headers["X-BC-Timestamp"].to_str().unwrap();
+        assert!(header.bytes().all(|b| b.is_ascii_digit()), "{}", header);
+        let timestamp: i64 = header.parse().unwrap();
+        assert!((before..before + 5).contains(&timestamp), "{}", header);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_request_covers_body() -> Result<(), BaichuanError> {
+        let data = BaichuanReq {
+            model: Model::Baichuan2_53B,
+            messages: vec![ChatMessage::user("1")],
+            parameters: Parameters::default(),
+            tools: vec![],
+        };
+        let url = endpoint(DEFAULT_BASE_URL, CHAT_PATH);
+        let signed = sign_request(
+            url.clone(),
+            "api key",
+            "secret key",
+            SignAlgo::Md5,
+            &data,
+            None,
+        )?;
+        let header = |name: &str| signed.headers[name].to_str().unwrap().to_string();
+        let expected = md5_hash(&format!(
+            "secret key{}{}",
+            signed.body,
+            header("X-BC-Timestamp")
+        ));
+        assert_eq!(header("X-BC-Signature"), expected);
+        assert_eq!(header("X-BC-Request-Id"), signed.request_id);
+        let again = sign_request(
+            url,
+            "api key",
+            "secret key",
+            SignAlgo::Md5,
+            &data,
+            Some(&signed.request_id),
+        )?;
+        assert_eq!(again.headers["X-BC-Request-Id"], header("X-BC-Request-Id"));
         Ok(())
     }
 
+    #[test]
+    fn test_validate_credentials() {
+        assert!(validate_credentials("api", "secret").is_ok());
+        let err = validate_credentials("", "secret").unwrap_err();
+        assert_eq!(err.to_string(), "invalid credentials: api key is empty");
+        let err = validate_credentials("api", "sec ret").unwrap_err();
+        assert!(err.to_string().contains("secret key"));
+    }
+
+    #[test]
+    fn test_http_client_proxy() {
+        let options = HttpOptions {
+            proxy: Some("socks5://127.0.0.1:1080".into()),
+            ..HttpOptions::default()
+        };
+        assert!(http_client(&options).is_ok());
+        let options = HttpOptions {
+            proxy: Some("not a url".into()),
+            ..HttpOptions::default()
+        };
+        let err = http_client(&options).unwrap_err();
+        assert!(err.to_string().contains("invalid proxy"));
+        let options = HttpOptions {
+            timeout: None,
+            read_timeout: Some(Duration::from_secs(30)),
+            ..HttpOptions::default()
+        };
+        assert!(http_client(&options).is_ok());
+        assert!(!HttpOptions::default().danger_accept_invalid_certs);
+        let options = HttpOptions {
+            keepalive: Some(Duration::from_secs(30)),
+            ..HttpOptions::default()
+        };
+        assert!(http_client(&options).is_ok());
+    }
+
+    #[test]
+    fn test_base_url() {
+        assert!(validate_base_url(DEFAULT_BASE_URL).is_ok());
+        assert!(validate_base_url("http://localhost:8080").is_ok());
+        assert!(validate_base_url("localhost:8080").is_err());
+        assert!(validate_base_url("not a url").is_err());
+        assert_eq!(
+            endpoint("http://localhost:8080/", CHAT_PATH),
+            "http://localhost:8080/v1/chat"
+        );
+    }
+
+    #[test]
+    fn test_sha256_hash() {
+        assert_eq!(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            sha256_hash("hello world")
+        );
+    }
+
+    #[test]
+    fn test_model_serde() {
+        for (model, name) in [
+            (Model::Baichuan2_53B, "Baichuan2-53B"),
+            (Model::Baichuan2_13B, "Baichuan2-13B"),
+            (Model::Baichuan2Turbo, "Baichuan2-Turbo"),
+            (Model::Baichuan2Turbo192k, "Baichuan2-Turbo-192k"),
+        ] {
+            let text = serde_json::to_string(&model).expect("cannot serialize");
+            assert_eq!(text, format!("\"{}\"", name));
+            let parsed: Model = serde_json::from_str(&text).expect("cannot parse");
+            assert_eq!(parsed, model);
+            assert_eq!(Model::from_str(name, false), Ok(model));
+            assert_eq!(model.to_string(), name);
+        }
+    }
+
     #[test]
     fn test_resp_deser() {
         let text = r#"{"code":0,"msg":"success","data":{"messages":[{"role":"assistant","content":"你好！很高兴为您提供帮助。请问您有什么问题需要我解答？","finish_reason":"stop"}]},"usage":{"prompt_tokens":3,"answer_tokens":15,"total_tokens":18}}"#;
@@ -205,4 +1308,372 @@ mod test {
         assert!(parsed.data.is_some());
         assert_eq!(parsed.data.unwrap().messages.len(), 1);
     }
+
+    #[test]
+    fn test_usage_display() {
+        let mut usage = UsageInfo {
+            prompt_tokens: 3,
+            answer_tokens: 15,
+            total_tokens: 18,
+        };
+        assert_eq!(usage.to_string(), "prompt=3 answer=15 total=18");
+        usage += &usage.clone();
+        assert_eq!(usage.to_string(), "prompt=6 answer=30 total=36");
+    }
+
+    #[test]
+    fn test_resp_ser_keeps_usage() {
+        let text = r#"{"code":0,"msg":"success"}"#;
+        let parsed: BaichuanResp = serde_json::from_str(text).expect("cannot parse");
+        let value = serde_json::to_value(&parsed).expect("cannot serialize");
+        assert_eq!(value["code"], 0);
+        assert!(value.get("usage").is_some());
+    }
+
+    #[test]
+    fn test_was_truncated() {
+        let mut message = ChatMessage::assistant("很长的回答");
+        assert!(!message.was_truncated());
+        message.finish_reason = Some("stop".into());
+        assert!(!message.was_truncated());
+        message.finish_reason = Some("length".into());
+        assert!(message.was_truncated());
+        message.cancel();
+        assert!(message.was_cancelled());
+        assert!(!message.was_truncated());
+    }
+
+    #[test]
+    fn test_with_system_prompt() {
+        let messages = with_system_prompt(Some("be brief"), vec![ChatMessage::user("hi")]);
+        let roles: Vec<_> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user"]);
+        assert_eq!(
+            with_system_prompt(None, vec![ChatMessage::user("hi")]).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_parameters_ser() {
+        let parameters = Parameters {
+            temperature: Some(0.5),
+            top_k: Some(5),
+            max_tokens: Some(256),
+            seed: Some(42),
+            ..Parameters::default()
+        };
+        let text = serde_json::to_string(&parameters).expect("cannot serialize");
+        assert_eq!(
+            text,
+            r#"{"temperature":0.5,"top_k":5,"max_tokens":256,"seed":42}"#
+        );
+    }
+
+    #[test]
+    fn test_answer_json() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct City {
+            name: String,
+            population: u64,
+        }
+        let resp = |answer: &str| BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: vec![ChatMessage::assistant(answer)],
+            }),
+            usage: None,
+        };
+        let city = City {
+            name: "北京".to_string(),
+            population: 21_890_000,
+        };
+        let json = r#"{"name": "北京", "population": 21890000}"#;
+        assert_eq!(resp(json).answer_json::<City>().unwrap(), city);
+        let fenced = format!("```json\n{}\n```", json);
+        assert_eq!(resp(&fenced).answer_json::<City>().unwrap(), city);
+        match resp("北京有2189万人").answer_json::<City>() {
+            Err(BaichuanError::AnswerNotJson { answer, .. }) => {
+                assert_eq!(answer, "北京有2189万人")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let json = serde_json::to_value(Parameters {
+            response_format: Some(ResponseFormat::JsonObject),
+            ..Parameters::default()
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"response_format": {"type": "json_object"}})
+        );
+    }
+
+    #[test]
+    fn test_resp_display() {
+        let resp = BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: vec![ChatMessage::assistant("你好")],
+            }),
+            usage: Some(UsageInfo {
+                prompt_tokens: 3,
+                answer_tokens: 5,
+                total_tokens: 8,
+            }),
+        };
+        assert_eq!(
+            resp.to_string(),
+            "[assistant]: 你好\n[usage] prompt=3 answer=5 total=8"
+        );
+        let resp = BaichuanResp {
+            code: RespCode::AccountBalanceInsufficient,
+            msg: "Insufficient account balance".to_string(),
+            data: None,
+            usage: None,
+        };
+        assert_eq!(
+            resp.to_string(),
+            "AccountBalanceInsufficient: Insufficient account balance"
+        );
+    }
+
+    #[test]
+    fn test_resp_without_usage() {
+        let text = r#"{"code":0,"msg":"success","data":{"messages":[{"role":"assistant","content":"你好","finish_reason":"stop"}]}}"#;
+        let resp: BaichuanResp = serde_json::from_str(text).expect("cannot parse");
+        assert!(resp.usage.is_none());
+        assert_eq!(resp.answer(), "你好");
+        assert_eq!(
+            resp.to_string(),
+            "[assistant]: 你好\n[usage] (usage unavailable)"
+        );
+    }
+
+    #[test]
+    fn test_extra_parameters() -> Result<(), BaichuanError> {
+        let parameters = Parameters {
+            temperature: Some(0.5),
+            extra: BTreeMap::from([
+                ("temperature".to_string(), serde_json::json!(0.9)),
+                ("repetition_penalty".to_string(), serde_json::json!(1.1)),
+            ]),
+            ..Parameters::default()
+        };
+        let request = build_request(Model::Baichuan2Turbo, vec![], &parameters)?;
+        let json = serde_json::to_value(&request).map_err(BaichuanError::Serialization)?;
+        assert_eq!(
+            json["parameters"],
+            serde_json::json!({"temperature": 0.5, "repetition_penalty": 1.1})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_knowledge_base_is_a_tool() -> Result<(), BaichuanError> {
+        let parameters = Parameters {
+            knowledge_base_id: Some("kb-123".to_string()),
+            ..Parameters::default()
+        };
+        let request = build_request(Model::Baichuan2Turbo, vec![], &parameters)?;
+        let json = serde_json::to_value(&request).map_err(BaichuanError::Serialization)?;
+        assert_eq!(json["parameters"], serde_json::json!({}));
+        assert_eq!(
+            json["tools"],
+            serde_json::json!([{"type": "retrieval", "retrieval": {"kb_ids": ["kb-123"]}}])
+        );
+        let request = build_request(Model::Baichuan2Turbo, vec![], &Parameters::default())?;
+        let json = serde_json::to_value(&request).map_err(BaichuanError::Serialization)?;
+        assert!(json.get("tools").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parameters_validate() {
+        let parameters = Parameters {
+            temperature: Some(1.5),
+            ..Parameters::default()
+        };
+        assert!(parameters.validate().is_err());
+        let parameters = Parameters {
+            max_tokens: Some(0),
+            ..Parameters::default()
+        };
+        assert!(parameters.validate().is_err());
+        let parameters = Parameters {
+            n: Some(0),
+            ..Parameters::default()
+        };
+        assert!(parameters.validate().is_err());
+        let parameters = Parameters {
+            repetition_penalty: Some(0.9),
+            ..Parameters::default()
+        };
+        assert!(parameters.validate().is_err());
+        let parameters = Parameters {
+            stop: Some(vec!["\n\n".to_string(), String::new()]),
+            ..Parameters::default()
+        };
+        assert!(parameters.validate().is_err());
+        assert!(Parameters::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_is_safety_violation() {
+        assert!(RespCode::PromptNotSafe.is_safety_violation());
+        assert!(RespCode::AnswerNotSafe.is_safety_violation());
+        assert!(!RespCode::InvalidApikey.is_safety_violation());
+    }
+
+    #[test]
+    fn test_api_error_hint() {
+        let text = r#"{"code":10300,"msg":"Insufficient account balance, please recharge."}"#;
+        let parsed: BaichuanResp = serde_json::from_str(text).expect("cannot parse");
+        let err = check_resp(parsed, None).expect_err("should be rejected");
+        assert_eq!(
+            err.to_string(),
+            "request failed with AccountBalanceInsufficient: Insufficient account balance, \
+             please recharge. Top up your account balance at https://platform.baichuan-ai.com."
+        );
+    }
+
+    #[test]
+    fn test_check_resp() {
+        let text = r#"{"code":10203,"msg":"Request too frequent, please try again later"}"#;
+        let parsed: BaichuanResp = serde_json::from_str(text).expect("cannot parse");
+        let err = check_resp(parsed, Some("req-1")).expect_err("should be rejected");
+        assert_eq!(err.resp_code(), Some(RespCode::AccountRequestTooFrequent));
+        assert_eq!(err.request_id(), Some("req-1"));
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let mut headers = HeaderMap::new();
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(clock_skew(&headers, now), None);
+        headers.insert(
+            reqwest::header::DATE,
+            "Tue, 02 Jan 2024 03:02:05 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            clock_skew(&headers, now),
+            Some(chrono::Duration::seconds(120))
+        );
+    }
+
+    #[test]
+    fn test_response_request_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(response_request_id(&headers, "sent".to_string()), "sent");
+        headers.insert("X-BC-Request-Id", "echoed".parse().unwrap());
+        assert_eq!(response_request_id(&headers, "sent".to_string()), "echoed");
+    }
+
+    /// Answers every request with the same body, echoing a fixed request id.
+    struct CannedTransport(String);
+
+    impl Transport for CannedTransport {
+        async fn post_json(
+            &self,
+            _url: &str,
+            _headers: HeaderMap,
+            _body: Vec<u8>,
+            _timeout: Option<Duration>,
+        ) -> Result<TransportResponse, BaichuanError> {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-BC-Request-Id", "server-req-id".parse().unwrap());
+            Ok(TransportResponse {
+                headers,
+                body: self.0.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    async fn canned_request(body: &str) -> Result<BaichuanResp, BaichuanError> {
+        make_baichuan_request(
+            &CannedTransport(body.to_string()),
+            "api_key",
+            "secret_key",
+            Model::Baichuan2_53B,
+            vec!["你好".to_string()],
+            &Parameters::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_request_success() -> Result<(), BaichuanError> {
+        let resp = canned_request(
+            r#"{"code":0,"msg":"success","data":{"messages":[{"role":"assistant","content":"你好！","finish_reason":"stop"}]}}"#,
+        )
+        .await?;
+        assert_eq!(resp.answer(), "你好！");
+        assert_eq!(resp.data.expect("data").messages[0].content, "你好！");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_error_codes() {
+        for (code, msg) in [
+            (
+                RespCode::AccountBalanceInsufficient,
+                "Insufficient account balance",
+            ),
+            (RespCode::PromptNotSafe, "The prompt is not safe"),
+            (RespCode::InvalidSignature, "Invalid signature"),
+        ] {
+            let body = format!(r#"{{"code":{},"msg":"{}"}}"#, code as i32, msg);
+            match canned_request(&body).await {
+                Err(BaichuanError::Api {
+                    code: got,
+                    msg: got_msg,
+                    request_id,
+                }) => {
+                    assert_eq!(got, code);
+                    assert_eq!(got_msg, msg);
+                    assert_eq!(request_id.as_deref(), Some("server-req-id"));
+                }
+                other => panic!("expected an api error, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_empty_success() -> Result<(), BaichuanError> {
+        let resp = canned_request(r#"{"code":0,"msg":"success"}"#).await?;
+        assert!(resp.is_empty());
+        let resp = canned_request(r#"{"code":0,"msg":"success","data":{"messages":[]}}"#).await?;
+        assert!(resp.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_bad_json() {
+        let err = canned_request("<html>502 Bad Gateway</html>")
+            .await
+            .expect_err("should fail to parse");
+        match err {
+            BaichuanError::Parse { body_snippet, .. } => {
+                assert_eq!(body_snippet, "<html>502 Bad Gateway</html>")
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_snippet_is_truncated() {
+        let body = "错".repeat(1000);
+        let err = parse_resp(body.as_bytes(), None).expect_err("should fail to parse");
+        match err {
+            BaichuanError::Parse { body_snippet, .. } => {
+                assert_eq!(body_snippet.chars().count(), 201);
+                assert!(body_snippet.ends_with('…'));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
 }
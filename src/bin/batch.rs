@@ -0,0 +1,135 @@
+//! `--batch`: answers every line of a file as an independent prompt.
+
+use crate::input::{json_lines, InputFormat};
+use baichuan_cli::{BaichuanClient, ChatMessage, UsageInfo};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+
+/// The outcome for one line of the batch file, with `error` set instead of
+/// `answer` if the request failed.
+#[derive(Serialize, Debug)]
+pub struct BatchResult {
+    /// 1-based line number in the batch file.
+    pub line: usize,
+    /// The last message of the line's conversation.
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The non-blank lines of `text` with their line numbers.
+pub fn prompts(text: &str) -> Vec<(usize, String)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect()
+}
+
+/// The conversations of the batch file, one per non-blank line.
+pub fn conversations(
+    text: &str,
+    format: InputFormat,
+) -> Result<Vec<(usize, Vec<ChatMessage>)>, String> {
+    match format {
+        InputFormat::Text => Ok(prompts(text)
+            .into_iter()
+            .map(|(line, prompt)| (line, vec![ChatMessage::user(prompt)]))
+            .collect()),
+        InputFormat::Jsonl => {
+            let conversations: Vec<(usize, Vec<ChatMessage>)> = json_lines(text)?;
+            match conversations
+                .iter()
+                .find(|(_, messages)| messages.is_empty())
+            {
+                Some((line, _)) => Err(format!("line {}: no messages", line)),
+                None => Ok(conversations),
+            }
+        }
+    }
+}
+
+/// Sends up to `concurrency` conversations at a time. A failed prompt is recorded
+/// in its result instead of stopping the batch. Results are ordered by line.
+pub async fn run(
+    client: &BaichuanClient,
+    conversations: Vec<(usize, Vec<ChatMessage>)>,
+    concurrency: usize,
+) -> Vec<BatchResult> {
+    let mut results: Vec<BatchResult> = results(client, conversations, concurrency).collect().await;
+    results.sort_by_key(|result| result.line);
+    results
+}
+
+/// Like [`run`], but yielding each result as soon as its request completes,
+/// so not in the order of the lines.
+pub fn results<'a>(
+    client: &'a BaichuanClient,
+    conversations: Vec<(usize, Vec<ChatMessage>)>,
+    concurrency: usize,
+) -> impl Stream<Item = BatchResult> + 'a {
+    stream::iter(conversations)
+        .map(move |(line, messages)| async move {
+            let prompt = messages
+                .last()
+                .map(|message| message.content.clone())
+                .unwrap_or_default();
+            let resp = client.send(messages).await;
+            match resp {
+                Ok(resp) => {
+                    let answer = resp
+                        .data
+                        .and_then(|data| data.messages.into_iter().next())
+                        .map(|message| message.content);
+                    BatchResult {
+                        line,
+                        prompt,
+                        answer,
+                        usage: resp.usage,
+                        error: None,
+                    }
+                }
+                Err(e) => BatchResult {
+                    line,
+                    prompt,
+                    answer: None,
+                    usage: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prompts_skip_blank_lines() {
+        let prompts = prompts("你好\n\n  \nWhat is Rust?\n");
+        assert_eq!(
+            prompts,
+            vec![(1, "你好".to_string()), (4, "What is Rust?".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_jsonl_conversations() {
+        let text = r#"[{"role": "user", "content": "你好"}]
+[{"role": "user", "content": "a"}, {"role": "assistant", "content": "b"}, {"role": "user", "content": "c"}]
+"#;
+        let parsed = conversations(text, InputFormat::Jsonl).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].0, 2);
+        assert_eq!(parsed[1].1.len(), 3);
+        assert_eq!(
+            conversations("[]", InputFormat::Jsonl).unwrap_err(),
+            "line 1: no messages"
+        );
+    }
+}
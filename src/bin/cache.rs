@@ -0,0 +1,77 @@
+//! `--cache`: answers to identical requests are reused instead of paid for
+//! again.
+
+use baichuan_cli::BaichuanResp;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Responses stored as one JSON file per request in a directory.
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl Cache {
+    /// Entries older than `ttl` are ignored, and replaced once the request
+    /// has been sent again.
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Cache { dir, ttl }
+    }
+
+    /// The key of a request: a hash of its body, which holds the model, the
+    /// messages and the parameters but none of the per-request headers.
+    pub fn key(body: &str) -> String {
+        format!("{:x}", Sha256::digest(body))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// The stored response, if there is one younger than the TTL.
+    pub fn get(&self, key: &str) -> Option<BaichuanResp> {
+        let path = self.path(key);
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age >= self.ttl {
+            return None;
+        }
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn put(&self, key: &str, resp: &BaichuanResp) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(key), serde_json::to_string(resp)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use baichuan_cli::{BaichuanData, ChatMessage, RespCode};
+
+    #[test]
+    fn test_round_trip_and_ttl() {
+        let dir = std::env::temp_dir().join(format!("bc-cli-cache-{}", std::process::id()));
+        let resp = BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: vec![ChatMessage::assistant("你好")],
+            }),
+            usage: None,
+        };
+        let cache = Cache::new(dir.clone(), Duration::from_secs(60));
+        assert!(cache.get("key").is_none());
+        cache.put("key", &resp).unwrap();
+        let cached = cache.get("key").unwrap();
+        assert_eq!(cached.data.unwrap().messages[0].content, "你好");
+        assert!(Cache::new(dir.clone(), Duration::ZERO).get("key").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
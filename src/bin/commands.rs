@@ -0,0 +1,252 @@
+//! Slash commands the REPL handles itself instead of sending them to the model.
+
+use baichuan_cli::{Model, Parameters};
+use clap::ValueEnum;
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub const HELP: &str = "\
+/clear              forget the conversation so far
+/get NAME           show a sampling parameter
+/help               show this list
+/model [NAME]       show the current model, or switch to NAME
+/params             show all sampling parameters
+/set NAME VALUE     change a sampling parameter for the next requests, to
+                    the API default with VALUE default, for stop to a
+                    comma separated list
+/tokens             estimate the tokens and cost of sending the conversation
+/usage              show the tokens used in this session
+TEXT /cancel        discard the line instead of sending it, /cancel being
+                    the default --cancel-keyword";
+
+/// The value `/set` takes to unset a parameter, so that the API default
+/// applies.
+pub const UNSET: &str = "default";
+
+/// Ending a line with this discards it, unless --cancel-keyword says otherwise.
+pub const DEFAULT_CANCEL_KEYWORD: &str = "/cancel";
+
+/// The parameters `/set` and `/get` know, as named in the config file.
+pub const PARAMETERS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "top_k",
+    "repetition_penalty",
+    "max_tokens",
+    "stop",
+    "seed",
+    "n",
+    "with_search_enhance",
+    "knowledge_base_id",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Clear,
+    Help,
+    /// Show the current model, or switch to the given one.
+    Model(Option<Model>),
+    /// Show the sampling parameter of that name.
+    Get(String),
+    /// Set the sampling parameter of the given name to the given value.
+    Set(String, String),
+    Params,
+    Tokens,
+    Usage,
+}
+
+impl Command {
+    /// Parses a REPL line. Returns `None` if the line is a prompt rather than
+    /// a command, and an error message for unknown or malformed commands.
+    pub fn parse(line: &str) -> Option<Result<Command, String>> {
+        let line = line.trim().strip_prefix('/')?;
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let command = match (name, args.as_slice()) {
+            ("clear", []) => Ok(Command::Clear),
+            ("help", []) => Ok(Command::Help),
+            ("tokens", []) => Ok(Command::Tokens),
+            ("usage", []) => Ok(Command::Usage),
+            ("model", []) => Ok(Command::Model(None)),
+            ("model", [model]) => Model::from_str(model, true)
+                .map(|model| Command::Model(Some(model)))
+                .map_err(|_| format!("unknown model {}, expected one of {}", model, model_names())),
+            ("params", []) => Ok(Command::Params),
+            ("get", [name]) => parameter_name(name).map(Command::Get),
+            // the value may have spaces, as a stop text can
+            ("set", [name, value @ ..]) if !value.is_empty() => {
+                parameter_name(name).map(|name| Command::Set(name, value.join(" ")))
+            }
+            ("get", _) => Err("usage: /get NAME".to_string()),
+            ("set", _) => Err("usage: /set NAME VALUE".to_string()),
+            ("clear" | "help" | "params" | "tokens" | "usage" | "model", _) => {
+                Err(format!("too many arguments for /{}", name))
+            }
+            _ => Err(format!("unknown command /{}, try /help", name)),
+        };
+        Some(command)
+    }
+}
+
+/// Whether `line` ends with `keyword`, so is to be discarded. An empty
+/// keyword never matches.
+pub fn is_cancelled(line: &str, keyword: &str) -> bool {
+    !keyword.is_empty() && line.trim_end().ends_with(keyword)
+}
+
+fn parameter_name(name: &str) -> Result<String, String> {
+    if PARAMETERS.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!(
+            "unknown parameter {}, expected one of {}",
+            name,
+            PARAMETERS.join(", ")
+        ))
+    }
+}
+
+/// The value of the parameter `name`, `None` if it isn't set.
+pub fn get_parameter(parameters: &Parameters, name: &str) -> Option<String> {
+    fn show<T: Display>(value: &Option<T>) -> Option<String> {
+        value.as_ref().map(T::to_string)
+    }
+    match name {
+        "temperature" => show(&parameters.temperature),
+        "top_p" => show(&parameters.top_p),
+        "top_k" => show(&parameters.top_k),
+        "repetition_penalty" => show(&parameters.repetition_penalty),
+        "max_tokens" => show(&parameters.max_tokens),
+        "stop" => parameters.stop.as_ref().map(|stop| stop.join(",")),
+        "seed" => show(&parameters.seed),
+        "n" => show(&parameters.n),
+        "with_search_enhance" => show(&parameters.with_search_enhance),
+        "knowledge_base_id" => show(&parameters.knowledge_base_id),
+        _ => None,
+    }
+}
+
+/// Sets the parameter `name` to `value`, or unsets it if `value` is
+/// [`UNSET`], leaving `parameters` unchanged if the value can't be parsed or
+/// is out of range.
+pub fn set_parameter(parameters: &mut Parameters, name: &str, value: &str) -> Result<(), String> {
+    fn parse<T: FromStr>(name: &str, value: Option<&str>) -> Result<Option<T>, String>
+    where
+        T::Err: Display,
+    {
+        value
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| format!("invalid {} {:?}: {}", name, value, e))
+            })
+            .transpose()
+    }
+    let value = Some(value).filter(|&value| value != UNSET);
+    let mut changed = parameters.clone();
+    match name {
+        "temperature" => changed.temperature = parse(name, value)?,
+        "top_p" => changed.top_p = parse(name, value)?,
+        "top_k" => changed.top_k = parse(name, value)?,
+        "repetition_penalty" => changed.repetition_penalty = parse(name, value)?,
+        "max_tokens" => changed.max_tokens = parse(name, value)?,
+        "stop" => changed.stop = value.map(|value| value.split(',').map(str::to_string).collect()),
+        "seed" => changed.seed = parse(name, value)?,
+        "n" => changed.n = parse(name, value)?,
+        "with_search_enhance" => changed.with_search_enhance = parse(name, value)?,
+        "knowledge_base_id" => changed.knowledge_base_id = value.map(str::to_string),
+        _ => return Err(parameter_name(name).unwrap_err()),
+    }
+    changed.validate().map_err(|e| e.to_string())?;
+    *parameters = changed;
+    Ok(())
+}
+
+fn model_names() -> String {
+    Model::value_variants()
+        .iter()
+        .map(|model| model.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("你好"), None);
+        assert_eq!(Command::parse(" /clear "), Some(Ok(Command::Clear)));
+        assert_eq!(Command::parse("/model"), Some(Ok(Command::Model(None))));
+        assert_eq!(
+            Command::parse("/model baichuan2-13b"),
+            Some(Ok(Command::Model(Some(Model::Baichuan2_13B))))
+        );
+        assert!(matches!(Command::parse("/model gpt-4"), Some(Err(_))));
+        assert!(matches!(Command::parse("/usage now"), Some(Err(_))));
+        assert!(matches!(Command::parse("/quit"), Some(Err(_))));
+        assert_eq!(
+            Command::parse("/set top_p 0.9"),
+            Some(Ok(Command::Set("top_p".into(), "0.9".into())))
+        );
+        assert_eq!(
+            Command::parse("/get top_p 0.9"),
+            Some(Err("usage: /get NAME".into()))
+        );
+        assert!(matches!(Command::parse("/set top_q 0.9"), Some(Err(_))));
+        assert_eq!(
+            Command::parse("/set stop 问题: ,END"),
+            Some(Ok(Command::Set("stop".into(), "问题: ,END".into())))
+        );
+        assert_eq!(
+            Command::parse("/set stop"),
+            Some(Err("usage: /set NAME VALUE".into()))
+        );
+    }
+
+    #[test]
+    fn test_is_cancelled() {
+        assert!(is_cancelled("写一首诗 /cancel", DEFAULT_CANCEL_KEYWORD));
+        assert!(is_cancelled("/cancel  ", DEFAULT_CANCEL_KEYWORD));
+        assert!(!is_cancelled("/cancel the order?", DEFAULT_CANCEL_KEYWORD));
+        assert!(is_cancelled("oops!!", "!!"));
+        assert!(!is_cancelled("anything", ""));
+    }
+
+    #[test]
+    fn test_set_parameter() {
+        let mut parameters = Parameters::default();
+        set_parameter(&mut parameters, "temperature", "0.3").unwrap();
+        assert_eq!(parameters.temperature, Some(0.3));
+        assert_eq!(
+            get_parameter(&parameters, "temperature").as_deref(),
+            Some("0.3")
+        );
+        // Invalid values leave the current one.
+        assert!(set_parameter(&mut parameters, "temperature", "1.5").is_err());
+        assert!(set_parameter(&mut parameters, "temperature", "hot").is_err());
+        assert_eq!(parameters.temperature, Some(0.3));
+        assert_eq!(get_parameter(&parameters, "top_k"), None);
+        set_parameter(&mut parameters, "temperature", UNSET).unwrap();
+        assert_eq!(parameters.temperature, None);
+    }
+
+    #[test]
+    fn test_set_stop() {
+        let mut parameters = Parameters {
+            stop: Some(vec!["\n\n".into()]),
+            ..Parameters::default()
+        };
+        set_parameter(&mut parameters, "stop", "问题:,END").unwrap();
+        assert_eq!(parameters.stop, Some(vec!["问题:".into(), "END".into()]));
+        assert_eq!(
+            get_parameter(&parameters, "stop").as_deref(),
+            Some("问题:,END")
+        );
+        assert!(set_parameter(&mut parameters, "stop", "END,").is_err());
+        set_parameter(&mut parameters, "stop", UNSET).unwrap();
+        assert_eq!(parameters.stop, None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Defaults read from `~/.config/baichuan_cli/config.toml`.
+
+use baichuan_cli::{Model, Parameters};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Settings that can be stored instead of passed on every invocation. Flags
+/// given on the command line take precedence over the file, which takes
+/// precedence over environment variables. The keys are redacted in its
+/// `Debug` output.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub api_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub model: Option<Model>,
+    /// More key pairs to use in turn with `api_key`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_keys: Vec<KeyPair>,
+    #[serde(default)]
+    pub parameters: Parameters,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPair {
+    pub api_key: String,
+    pub secret_key: String,
+}
+
+impl KeyPair {
+    /// Keeps only the last characters of the api key, enough to tell keys
+    /// apart, and none of the secret key.
+    pub fn redacted(&self) -> KeyPair {
+        KeyPair {
+            api_key: redact_api_key(&self.api_key),
+            secret_key: REDACTED.to_string(),
+        }
+    }
+}
+
+const REDACTED: &str = "********";
+
+fn redact_api_key(api_key: &str) -> String {
+    let chars: Vec<char> = api_key.chars().collect();
+    let tail: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+    format!("{}{}", REDACTED, tail)
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("api_key", &redact_api_key(&self.api_key))
+            .field("secret_key", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("api_key", &self.api_key.as_deref().map(redact_api_key))
+            .field("secret_key", &self.secret_key.as_ref().map(|_| REDACTED))
+            .field("model", &self.model)
+            .field("extra_keys", &self.extra_keys)
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/baichuan_cli/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("baichuan_cli").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config at `path`. A missing file is only an error if the
+    /// path was given explicitly.
+    pub fn load(path: &Path, explicit: bool) -> Result<Config, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+            Err(e) => return Err(format!("cannot read config {}: {}", path.display(), e)),
+        };
+        toml::from_str(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+
+    /// The config as TOML, in the format of the file.
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("cannot format config: {}", e))
+    }
+}
+
+/// Stores the credentials in the config at `path`, keeping its other
+/// settings. The file is only readable by its owner, as the keys are secret.
+pub fn save_credentials(path: &Path, api_key: &str, secret_key: &str) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("cannot save config {}: {}", path.display(), e);
+    let mut table = match fs::read_to_string(path) {
+        Ok(text) => toml::from_str::<toml::Table>(&text).map_err(|e| error(&e))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(error(&e)),
+    };
+    table.insert("api_key".to_string(), api_key.into());
+    table.insert("secret_key".to_string(), secret_key.into());
+    let text = toml::to_string(&table).map_err(|e| error(&e))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| error(&e))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(|e| error(&e))?;
+    file.write_all(text.as_bytes()).map_err(|e| error(&e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            api_key = "key"
+            model = "Baichuan2-Turbo"
+
+            [[extra_keys]]
+            api_key = "key 2"
+            secret_key = "secret 2"
+
+            [parameters]
+            temperature = 0.3
+            "#,
+        )
+        .expect("cannot parse");
+        assert_eq!(config.api_key.as_deref(), Some("key"));
+        assert_eq!(config.secret_key, None);
+        assert_eq!(config.model, Some(Model::Baichuan2Turbo));
+        assert_eq!(config.extra_keys[0].api_key, "key 2");
+        assert_eq!(config.parameters.temperature, Some(0.3));
+    }
+
+    #[test]
+    fn test_save_credentials_keeps_settings() {
+        let path = std::env::temp_dir().join(format!("bc-cli-config-{}.toml", std::process::id()));
+        fs::write(&path, "api_key = \"old\"\nmodel = \"Baichuan2-Turbo\"\n").unwrap();
+        save_credentials(&path, "key", "secret").unwrap();
+        let config = Config::load(&path, true).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("key"));
+        assert_eq!(config.secret_key.as_deref(), Some("secret"));
+        assert_eq!(config.model, Some(Model::Baichuan2Turbo));
+    }
+
+    #[test]
+    fn test_redacted_config() {
+        let key = KeyPair {
+            api_key: "sk-1234567890".to_string(),
+            secret_key: "secret".to_string(),
+        }
+        .redacted();
+        assert_eq!(key.api_key, "********7890");
+        assert_eq!(key.secret_key, "********");
+        let config = Config {
+            api_key: Some(key.api_key),
+            model: Some(Model::Baichuan2Turbo),
+            ..Config::default()
+        };
+        let text = config.to_toml().unwrap();
+        assert!(text.contains("model = \"Baichuan2-Turbo\""));
+        assert!(!text.contains("secret_key"));
+        assert!(!text.contains("extra_keys"));
+        // What is printed can be read back.
+        assert!(toml::from_str::<Config>(&text).is_ok());
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let key = KeyPair {
+            api_key: "sk-1234567890".to_string(),
+            secret_key: "secret".to_string(),
+        };
+        let config = Config {
+            api_key: Some("sk-abcdefghij".to_string()),
+            secret_key: Some("other-secret".to_string()),
+            extra_keys: vec![key],
+            ..Config::default()
+        };
+        let debug = format!("{:?}", config);
+        assert!(
+            debug.contains(r#"api_key: Some("********ghij")"#),
+            "{}",
+            debug
+        );
+        assert!(debug.contains(r#"api_key: "********7890""#), "{}", debug);
+        for secret in ["sk-1234567890", "secret\"", "sk-abcdefghij", "other-secret"] {
+            assert!(!debug.contains(secret), "{}", debug);
+        }
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        assert!(toml::from_str::<Config>("apikey = \"key\"").is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Keeping the conversation within the model's context window.
+
+use baichuan_cli::{
+    estimate_conversation_tokens, estimate_tokens, BaichuanClient, BaichuanError, BaichuanResp,
+    ChatMessage, UsageInfo,
+};
+use clap::ValueEnum;
+use std::future::Future;
+use tracing::warn;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum ContextStrategy {
+    /// Forget the oldest turns until the conversation fits
+    DropOldest,
+    /// Refuse to send the conversation
+    Error,
+    /// Have the model summarize the oldest turns, and keep the summary as a
+    /// system message in their place
+    Summarize,
+}
+
+/// How [`summarize`] made the conversation fit.
+#[derive(Debug, PartialEq)]
+pub struct Fitted {
+    /// How many messages were summarized or dropped.
+    pub messages: usize,
+    /// [`ContextStrategy::Summarize`], or [`ContextStrategy::DropOldest`] if
+    /// it fell back to dropping the oldest turns.
+    pub strategy: ContextStrategy,
+    /// What the summary request used, if one was answered with the usage.
+    pub usage: Option<UsageInfo>,
+}
+
+impl Fitted {
+    /// `messages` dropped by [`fit`].
+    pub fn dropped(messages: usize) -> Self {
+        Fitted {
+            messages,
+            strategy: ContextStrategy::DropOldest,
+            usage: None,
+        }
+    }
+
+    /// What was done to the messages, for telling the user.
+    pub fn verb(&self) -> &'static str {
+        match self.strategy {
+            ContextStrategy::Summarize => "summarized",
+            _ => "dropped",
+        }
+    }
+}
+
+/// Prefixes the summary kept in place of the turns it covers.
+pub const SUMMARY_PREFIX: &str = "Summary of the conversation so far: ";
+
+/// Asks for the summary, followed by the turns to summarize.
+const SUMMARIZE_PROMPT: &str = "Summarize the conversation below in a few sentences, \
+keeping the facts, names, decisions and open questions needed to continue it. \
+Reply with the summary only.";
+
+/// The tokens the conversation may take up: `explicit` if given, otherwise
+/// the model's context window less the room reserved for the answer by
+/// `max_tokens`. The system prompt always counts against it.
+pub fn budget(client: &BaichuanClient, explicit: Option<usize>) -> usize {
+    let budget = explicit.unwrap_or_else(|| {
+        let reserved = client.parameters().max_tokens.unwrap_or(0) as usize;
+        client.model().context_window().saturating_sub(reserved)
+    });
+    let system = client.system_prompt().map_or(0, estimate_tokens);
+    budget.saturating_sub(system)
+}
+
+/// Whether `message` is a summary left by [`summarize`].
+pub fn is_summary(message: &ChatMessage) -> bool {
+    message.role == "system" && message.content.starts_with(SUMMARY_PREFIX)
+}
+
+/// Makes `history` fit into `budget` estimated tokens according to
+/// `strategy`, returning how many messages were dropped. The summaries of
+/// [`ContextStrategy::Summarize`] are up to [`summarize`], here it drops the
+/// oldest turns after a leading summary, which only goes once they are all
+/// gone. The newest prompt is never dropped, so a single prompt over the
+/// budget is always an error.
+pub fn fit(
+    history: &mut Vec<ChatMessage>,
+    budget: usize,
+    strategy: ContextStrategy,
+) -> Result<usize, String> {
+    let mut dropped = 0;
+    loop {
+        let tokens = estimate_conversation_tokens(history);
+        if tokens <= budget {
+            return Ok(dropped);
+        }
+        if strategy == ContextStrategy::Error || history.len() <= 1 {
+            return Err(format!(
+                "the conversation is ~{} tokens, over the context budget of {}",
+                tokens, budget
+            ));
+        }
+        let summary = usize::from(is_summary(&history[0]));
+        if history.len() <= summary + 1 {
+            history.remove(0);
+            dropped += 1;
+            continue;
+        }
+        // drop the oldest turn, keeping the conversation starting with a
+        // user message
+        history.remove(summary);
+        let leading = history[summary..]
+            .iter()
+            .take_while(|m| m.role != "user")
+            .count();
+        history.drain(summary..summary + leading);
+        dropped += 1 + leading;
+    }
+}
+
+/// Makes `history` fit into `budget` estimated tokens by replacing its
+/// oldest turns, an earlier summary included, with a summary written by the
+/// model. The turns kept take up at most half the budget, so that the
+/// conversation can grow a while before the next summary.
+///
+/// The summary is asked for with `send`, in a request of its own bound by
+/// the model's `context_window` rather than the conversation's budget. Use
+/// [`BaichuanClient::send_plain`], so that the session's parameters and
+/// system prompt don't shape it. Falls back to dropping the oldest turns if
+/// the summary can't be had or doesn't fit itself.
+pub async fn summarize<F, Fut>(
+    history: &mut Vec<ChatMessage>,
+    budget: usize,
+    context_window: usize,
+    send: F,
+) -> Result<Fitted, String>
+where
+    F: FnOnce(Vec<ChatMessage>) -> Fut,
+    Fut: Future<Output = Result<BaichuanResp, BaichuanError>>,
+{
+    let drop_oldest = |history: &mut Vec<ChatMessage>, usage| {
+        let messages = fit(history, budget, ContextStrategy::DropOldest)?;
+        Ok(Fitted {
+            usage,
+            ..Fitted::dropped(messages)
+        })
+    };
+    if estimate_conversation_tokens(history) <= budget {
+        return Ok(Fitted::dropped(0));
+    }
+    let split = split(history, budget / 2);
+    if split == 0 {
+        return drop_oldest(history, None);
+    }
+    let mut oldest = history[..split].to_vec();
+    let request_budget = context_window.saturating_sub(estimate_tokens(SUMMARIZE_PROMPT));
+    let fitted = fit(&mut oldest, request_budget, ContextStrategy::DropOldest);
+    if fitted.is_err() || oldest.is_empty() {
+        return drop_oldest(history, None);
+    }
+    let request = format!("{}\n\n{}", SUMMARIZE_PROMPT, transcript(&oldest));
+    let resp = match send(vec![ChatMessage::user(request)]).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            warn!(
+                "Failed to summarize the conversation, dropping turns instead: {}",
+                e
+            );
+            return drop_oldest(history, None);
+        }
+    };
+    let summary = resp.answer().trim().to_string();
+    if summary.is_empty() {
+        return drop_oldest(history, resp.usage);
+    }
+    let before = history.len();
+    history.splice(
+        ..split,
+        [ChatMessage::system(format!(
+            "{}{}",
+            SUMMARY_PREFIX, summary
+        ))],
+    );
+    fit(history, budget, ContextStrategy::DropOldest)?;
+    // a summary too long to fit is dropped like the turns it covers
+    let kept = is_summary(&history[0]);
+    Ok(Fitted {
+        messages: before - (history.len() - usize::from(kept)),
+        strategy: if kept {
+            ContextStrategy::Summarize
+        } else {
+            ContextStrategy::DropOldest
+        },
+        usage: resp.usage,
+    })
+}
+
+/// Where the newest turns taking up at most `budget` tokens start, at a
+/// user message. The newest prompt is always kept, whatever its size.
+fn split(history: &[ChatMessage], budget: usize) -> usize {
+    let last = history.len().saturating_sub(1);
+    (0..last)
+        .filter(|&i| history[i].role == "user")
+        .find(|&i| estimate_conversation_tokens(&history[i..]) <= budget)
+        .unwrap_or(last)
+}
+
+/// `messages` as `role: content` paragraphs, for the model to summarize.
+fn transcript(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| format!("{}: {}", m.role, m.content))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use baichuan_cli::{MockTransport, RequestBuilder, RespCode, Transport, TransportResponse};
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::user("第一个问题"),
+            ChatMessage::assistant("第一个回答"),
+            ChatMessage::user("第二个问题"),
+        ]
+    }
+
+    #[test]
+    fn test_fit_drops_oldest_turn() {
+        let mut history = conversation();
+        assert_eq!(fit(&mut history, 10, ContextStrategy::DropOldest), Ok(2));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "第二个问题");
+    }
+
+    #[test]
+    fn test_fit_error_keeps_history() {
+        let mut history = conversation();
+        assert!(fit(&mut history, 10, ContextStrategy::Error).is_err());
+        assert_eq!(history.len(), 3);
+        assert_eq!(fit(&mut history, 1000, ContextStrategy::Error), Ok(0));
+    }
+
+    #[test]
+    fn test_split_keeps_newest_turns() {
+        let history = conversation();
+        let last_turn = estimate_conversation_tokens(&history[2..]);
+        assert_eq!(split(&history, last_turn), 2);
+        assert_eq!(split(&history, 0), 2);
+        assert_eq!(split(&history, 1000), 0);
+        assert_eq!(
+            transcript(&history[..2]),
+            "user: 第一个问题\n\nassistant: 第一个回答"
+        );
+    }
+
+    /// Many short turns and a budget they just don't fit into. The
+    /// transcript of short turns takes fewer tokens than the turns
+    /// themselves, so even the mock's echo of it makes room.
+    fn long_conversation() -> (Vec<ChatMessage>, usize) {
+        let mut history = Vec::new();
+        for _ in 0..60 {
+            history.push(ChatMessage::user("嗨"));
+            history.push(ChatMessage::assistant("好"));
+        }
+        history.push(ChatMessage::user("最后的问题"));
+        let budget = estimate_conversation_tokens(&history) - 1;
+        (history, budget)
+    }
+
+    /// Answers every request with a server failure.
+    struct Fails;
+
+    impl Transport for Fails {
+        async fn post_json(
+            &self,
+            _url: &str,
+            _headers: HeaderMap,
+            _body: Vec<u8>,
+            _timeout: Option<Duration>,
+        ) -> Result<TransportResponse, BaichuanError> {
+            let body = format!(
+                r#"{{"code":{},"msg":"failed"}}"#,
+                RespCode::InternalError as i32
+            );
+            Ok(TransportResponse {
+                headers: HeaderMap::new(),
+                body: body.into_bytes(),
+            })
+        }
+    }
+
+    async fn send(
+        transport: &impl Transport,
+        messages: Vec<ChatMessage>,
+    ) -> Result<BaichuanResp, BaichuanError> {
+        RequestBuilder::new(transport, "api key", "secret key")
+            .messages(messages)
+            .send()
+            .await
+    }
+
+    const WINDOW: usize = 4096;
+
+    #[test]
+    fn test_fit_keeps_summary() {
+        let mut history = conversation();
+        history.insert(
+            0,
+            ChatMessage::system(format!("{}早先的对话", SUMMARY_PREFIX)),
+        );
+        let budget = estimate_conversation_tokens(&[history[0].clone(), history[3].clone()]);
+        assert_eq!(
+            fit(&mut history, budget, ContextStrategy::DropOldest),
+            Ok(2)
+        );
+        assert!(is_summary(&history[0]));
+        assert_eq!(history[1].content, "第二个问题");
+        // the summary goes last, once there are no turns left to drop
+        assert_eq!(fit(&mut history, 10, ContextStrategy::DropOldest), Ok(1));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "第二个问题");
+    }
+
+    #[tokio::test]
+    async fn test_summarize() {
+        let (mut history, budget) = long_conversation();
+        let fitted = summarize(&mut history, budget, WINDOW, |messages| {
+            send(&MockTransport, messages)
+        })
+        .await
+        .unwrap();
+        assert_eq!(fitted.strategy, ContextStrategy::Summarize);
+        assert_eq!(fitted.verb(), "summarized");
+        assert!(fitted.usage.is_some());
+        assert_eq!(fitted.messages, 121 - (history.len() - 1));
+        assert!(estimate_conversation_tokens(&history) <= budget);
+        assert_eq!(history[0].role, "system");
+        // the mock echoes the request, which is the prompt and the transcript
+        assert!(history[0].content.starts_with(SUMMARY_PREFIX));
+        assert!(history[0].content.contains(SUMMARIZE_PROMPT));
+        assert_eq!(history[1].role, "user");
+        assert_eq!(history.last().unwrap().content, "最后的问题");
+    }
+
+    #[tokio::test]
+    async fn test_summarize_falls_back_to_dropping() {
+        let (mut history, budget) = long_conversation();
+        let fitted = summarize(&mut history, budget, WINDOW, |messages| {
+            send(&Fails, messages)
+        })
+        .await
+        .unwrap();
+        assert_eq!(fitted, Fitted::dropped(2));
+        assert_eq!(fitted.verb(), "dropped");
+        assert_eq!(history.len(), 119);
+        assert_eq!(history[0].role, "user");
+    }
+
+    #[tokio::test]
+    async fn test_summary_too_long_is_dropped() {
+        // the mock's echo of a few long turns is longer than they are
+        let mut history = conversation();
+        let budget = estimate_conversation_tokens(&history[2..]) + 1;
+        let fitted = summarize(&mut history, budget, WINDOW, |messages| {
+            send(&MockTransport, messages)
+        })
+        .await
+        .unwrap();
+        assert_eq!(fitted.strategy, ContextStrategy::DropOldest);
+        assert_eq!(fitted.messages, 2);
+        assert!(fitted.usage.is_some());
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "第二个问题");
+    }
+
+    #[tokio::test]
+    async fn test_summarize_within_budget() {
+        let mut history = conversation();
+        let fitted = summarize(&mut history, 1000, WINDOW, |messages| {
+            send(&MockTransport, messages)
+        })
+        .await
+        .unwrap();
+        assert_eq!(fitted, Fitted::dropped(0));
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn test_fit_single_prompt_over_budget() {
+        let mut history = vec![ChatMessage::user("太长的问题")];
+        assert!(fit(&mut history, 2, ContextStrategy::DropOldest).is_err());
+    }
+}
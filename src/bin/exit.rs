@@ -0,0 +1,91 @@
+//! The exit codes telling scripts what kind of failure ended a request,
+//! without parsing the error message. Listed in the README, so they must
+//! stay stable. 2 is taken by clap and the CLI's own checks for invalid
+//! arguments and input.
+
+use baichuan_cli::{BaichuanError, RespCode};
+use reqwest::StatusCode;
+
+/// Any failure not listed below.
+pub const FAILURE: i32 = 1;
+/// The credentials are missing, malformed or rejected, or the account can't
+/// be used.
+pub const AUTH: i32 = 3;
+/// The account is sending more requests than the API allows, or is locked
+/// for a while, which passes as well.
+pub const RATE_LIMITED: i32 = 4;
+/// The account balance doesn't cover the request.
+pub const BALANCE: i32 = 5;
+/// The prompt or the answer was blocked by the safety filter.
+pub const SAFETY: i32 = 6;
+
+/// The exit code for a request that failed with `e`.
+pub fn code(e: &BaichuanError) -> i32 {
+    if let BaichuanError::InvalidCredentials(_) = e {
+        return AUTH;
+    }
+    match (e.resp_code(), e.http_status()) {
+        (Some(code), _) if code.is_safety_violation() => SAFETY,
+        (Some(RespCode::AccountBalanceInsufficient), _) => BALANCE,
+        (Some(RespCode::AccountRequestTooFrequent | RespCode::AccountTempLocked), _) => {
+            RATE_LIMITED
+        }
+        (
+            Some(
+                RespCode::MissingApikey
+                | RespCode::InvalidApikey
+                | RespCode::ApikeyExpired
+                | RespCode::InvalidSignature
+                | RespCode::AccountNotFound
+                | RespCode::AccountLocked
+                | RespCode::AccountNotVerified,
+            ),
+            _,
+        ) => AUTH,
+        (_, Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => AUTH,
+        (_, Some(StatusCode::TOO_MANY_REQUESTS)) => RATE_LIMITED,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn api_error(code: RespCode) -> BaichuanError {
+        BaichuanError::Api {
+            code,
+            msg: String::new(),
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(code(&api_error(RespCode::InvalidApikey)), AUTH);
+        assert_eq!(
+            code(&api_error(RespCode::AccountRequestTooFrequent)),
+            RATE_LIMITED
+        );
+        assert_eq!(code(&api_error(RespCode::AccountTempLocked)), RATE_LIMITED);
+        assert_eq!(code(&api_error(RespCode::AccountLocked)), AUTH);
+        assert_eq!(
+            code(&api_error(RespCode::AccountBalanceInsufficient)),
+            BALANCE
+        );
+        assert_eq!(code(&api_error(RespCode::AnswerNotSafe)), SAFETY);
+        assert_eq!(code(&api_error(RespCode::InternalError)), FAILURE);
+        assert_eq!(
+            code(&BaichuanError::InvalidCredentials("empty".into())),
+            AUTH
+        );
+        let http = |status| BaichuanError::Http {
+            status,
+            body: String::new(),
+        };
+        assert_eq!(code(&http(StatusCode::UNAUTHORIZED)), AUTH);
+        assert_eq!(code(&http(StatusCode::TOO_MANY_REQUESTS)), RATE_LIMITED);
+        assert_eq!(code(&http(StatusCode::BAD_GATEWAY)), FAILURE);
+        assert_eq!(code(&BaichuanError::Config("bad".into())), FAILURE);
+    }
+}
@@ -0,0 +1,28 @@
+//! Where the REPL keeps the lines entered, for recalling them with the arrow
+//! keys in later sessions.
+
+use std::path::{Path, PathBuf};
+
+/// Where the history used to be kept, relative to the working directory.
+const LEGACY_PATH: &str = ".bc_cli_history";
+
+/// `$XDG_STATE_HOME/baichuan_cli/history`, falling back to `~/.local/state`.
+pub fn default_path() -> Option<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("baichuan_cli").join("history"))
+}
+
+/// The file to load the history at the default `path` from: the
+/// `.bc_cli_history` of older versions in the working directory until
+/// `path` has been written, so that the lines entered before carry over.
+pub fn load_path(path: &Path) -> &Path {
+    let legacy = Path::new(LEGACY_PATH);
+    if !path.exists() && legacy.is_file() {
+        legacy
+    } else {
+        path
+    }
+}
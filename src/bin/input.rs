@@ -0,0 +1,74 @@
+//! `--input-format`: how the prompt and --batch lines are read.
+
+use baichuan_cli::ChatMessage;
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum InputFormat {
+    /// The prompt is a single user message, --batch has one per line
+    Text,
+    /// The prompt is a conversation of one `{"role": ..., "content": ...}`
+    /// message per line, --batch has one JSON array of messages per line
+    Jsonl,
+}
+
+/// Parses every non-blank line of `text` as JSON, together with its 1-based
+/// line number.
+pub fn json_lines<T: DeserializeOwned>(text: &str) -> Result<Vec<(usize, T)>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map(|value| (i + 1, value))
+                .map_err(|e| format!("line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+/// The conversation to send for the prompt `text`.
+pub fn conversation(text: String, format: InputFormat) -> Result<Vec<ChatMessage>, String> {
+    match format {
+        InputFormat::Text => Ok(vec![ChatMessage::user(text)]),
+        InputFormat::Jsonl => {
+            let messages: Vec<ChatMessage> = json_lines(&text)?
+                .into_iter()
+                .map(|(_, message)| message)
+                .collect();
+            if messages.is_empty() {
+                return Err("no messages in the input".to_string());
+            }
+            Ok(messages)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jsonl_conversation() {
+        let text = r#"{"role": "user", "content": "你好"}
+
+{"role": "assistant", "content": "你好！"}
+{"role": "user", "content": "1+1=?"}
+"#;
+        let messages = conversation(text.to_string(), InputFormat::Jsonl).unwrap();
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+        assert_eq!(messages[2].content, "1+1=?");
+    }
+
+    #[test]
+    fn test_jsonl_errors() {
+        let text = "{\"role\": \"user\", \"content\": \"你好\"}\nnot json\n";
+        let err = conversation(text.to_string(), InputFormat::Jsonl).unwrap_err();
+        assert!(err.starts_with("line 2: "));
+        assert!(conversation("\n".to_string(), InputFormat::Jsonl).is_err());
+        // plain text is taken as is, even if it looks like JSON
+        let messages = conversation("{}".to_string(), InputFormat::Text).unwrap();
+        assert_eq!(messages[0].content, "{}");
+    }
+}
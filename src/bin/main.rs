@@ -1,48 +1,1768 @@
-use baichuan_cli::{make_baichuan_request, Model};
-use clap::Parser;
-use env_logger::Builder;
-use log::{debug, error, info, LevelFilter};
+mod batch;
+mod cache;
+mod commands;
+mod config;
+mod context;
+mod exit;
+mod history;
+mod input;
+mod out;
+mod replay;
+mod safety;
+mod session;
+mod stats;
+mod style;
+mod template;
+mod transcript;
+mod typewriter;
+mod wrap;
+
+use baichuan_cli::{
+    estimate_tokens, format_headers, http_client, load_session, save_session, set_wire_secrets,
+    validate_base_url, validate_credentials, BaichuanClient, BaichuanData, BaichuanError,
+    BaichuanResp, ChatMessage, HttpOptions, Model, Parameters, RespCode, ResponseFormat,
+    RetryPolicy, SignAlgo, SignedRequest, StreamEvent, UsageInfo, DEFAULT_BASE_URL,
+    DEFAULT_SLOW_REQUEST_THRESHOLD, DEFAULT_USER_AGENT, MAX_EMBEDDING_INPUTS, USAGE_UNAVAILABLE,
+    WIRE_TARGET,
+};
+use cache::Cache;
+use clap::parser::ValueSource;
+use clap::{
+    ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use commands::Command;
+use config::{Config, KeyPair};
+use context::ContextStrategy;
+use futures::StreamExt;
+use input::InputFormat;
+use replay::Replay;
 use rustyline::{error::ReadlineError, DefaultEditor, Result};
+use safety::Rephrase;
+use stats::LatencyStats;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+use transcript::Transcript;
+use wrap::Wrapper;
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(long, env)]
-    api_key: String,
-    #[arg(long, env)]
-    secret_key: String,
-    #[arg(short, long, value_enum, default_value_t = Model::Baichuan2_53B)]
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum Output {
+    /// `[role]: content`, streamed as the answer is generated
+    Text,
+    /// The full API response including token usage, one JSON object per turn
+    Json,
+    /// `role<TAB>content` per answer on a single line, with backslashes,
+    /// line breaks and tabs escaped as `\\`, `\n`, `\r` and `\t`
+    Compact,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum Render {
+    /// Print the answer as sent by the model
+    Plain,
+    /// Format markdown, printing the answer once it is complete
+    Markdown,
+}
+
+// Settings shared by all subcommands, accepted before or after the
+// subcommand name. Not a doc comment, as clap would take it for the about
+// text of the whole command. No `Debug`, as it holds the keys.
+#[derive(Args)]
+struct GlobalArgs {
+    #[arg(long, env, global = true)]
+    api_key: Option<String>,
+    #[arg(long, env, global = true)]
+    secret_key: Option<String>,
+    /// Another key pair to send requests with in turn, each with its own
+    /// rate limit. Can be repeated
+    #[arg(long = "extra-key", value_name = "API_KEY:SECRET_KEY", global = true, value_parser = parse_key_pair)]
+    extra_keys: Vec<KeyPair>,
+    /// TOML file with defaults for the credentials, model and parameters
+    /// [default: ~/.config/baichuan_cli/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(short, long, value_enum, global = true, default_value_t = Model::Baichuan2_53B)]
     model: Model,
-    #[arg(long, default_value_t = LevelFilter::Info)]
+    /// Model to try next when the server fails to answer with the ones
+    /// before it. Can be repeated
+    #[arg(
+        long = "fallback-model",
+        value_name = "MODEL",
+        value_enum,
+        global = true
+    )]
+    fallback_models: Vec<Model>,
+    #[arg(long, global = true, default_value_t = LevelFilter::INFO)]
     log_level: LevelFilter,
+    /// How many times a request is retried when rate limited or when the
+    /// account is temporarily locked
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: u32,
+    /// Give up on a request that hasn't completed after this many seconds,
+    /// 0 for no limit. This includes streamed answers
+    #[arg(long, global = true, default_value_t = HttpOptions::default().timeout.map_or(0, |t| t.as_secs()))]
+    timeout_secs: u64,
+    /// Give up on connecting to the server after this many seconds
+    #[arg(long, global = true, value_name = "SECS", default_value_t = HttpOptions::default().connect_timeout.as_secs())]
+    connect_timeout: u64,
+    /// Give up on a response when nothing has been received for this many
+    /// seconds, however long it takes overall
+    #[arg(long, global = true, value_name = "SECS")]
+    read_timeout: Option<u64>,
+    /// Warn about requests taking longer than this many seconds, 0 to never
+    /// warn. Streamed answers are exempt
+    #[arg(long, global = true, default_value_t = DEFAULT_SLOW_REQUEST_THRESHOLD.as_secs())]
+    slow_request_secs: u64,
+    /// HTTP or SOCKS5 proxy URL, overriding HTTPS_PROXY/ALL_PROXY
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+    /// User-Agent header sent with every request
+    #[arg(long, global = true, value_name = "AGENT", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+    /// Don't verify the server's TLS certificate, for gateways that
+    /// intercept TLS with their own. Whoever is in between can read the keys
+    /// and the conversation
+    #[arg(long, global = true)]
+    danger_accept_invalid_certs: bool,
+    /// Keep idle connections to the API open between requests, probing them
+    /// with TCP keepalives every SECS seconds, so that a prompt after a pause
+    /// is answered sooner. No requests are sent, so none are charged
+    #[arg(long, global = true, value_name = "SECS", num_args = 0..=1, require_equals = true, default_missing_value = "30")]
+    keepalive: Option<u64>,
+    /// Requests sent per minute at most with each api key, 0 for no limit.
+    /// The API allows 10 per api key
+    #[arg(long, global = true, default_value_t = 10)]
+    rpm: u32,
+    /// Digest used to sign requests
+    #[arg(long, value_enum, global = true, default_value_t = SignAlgo::Md5)]
+    sign_algo: SignAlgo,
+    /// API endpoint, e.g. a gateway or a local mock server
+    #[arg(long, env = "BAICHUAN_BASE_URL", global = true, default_value = DEFAULT_BASE_URL)]
+    base_url: String,
+    /// Don't compare the local clock with the server's at startup, which
+    /// warns if it is too far off for requests to be accepted
+    #[arg(long, global = true)]
+    no_clock_check: bool,
+    /// Answer locally by echoing the prompt instead of asking the API, to
+    /// try the CLI without network access or credentials
+    #[arg(long, global = true)]
+    mock: bool,
+    /// Never color the output, also implied by the NO_COLOR environment variable
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Don't redact the api key in --dry-run, --print-curl and --verbose
+    /// output, nor the signature in the --verbose logs
+    #[arg(long, global = true)]
+    show_secrets: bool,
+    /// Log the exact requests and responses exchanged with the API,
+    /// regardless of --log-level
+    #[arg(short, long, global = true)]
+    verbose: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("input").args(["prompt", "words", "stdin", "template"])))]
+struct ChatArgs {
+    /// Maximum number of messages kept as conversation context
+    #[arg(long, default_value_t = 20)]
+    max_history: usize,
+    /// Ending a line of the REPL with this discards it instead of sending it
+    #[arg(long, value_name = "KEYWORD", default_value = commands::DEFAULT_CANCEL_KEYWORD)]
+    cancel_keyword: String,
+    /// What to do when the conversation outgrows the context budget
+    #[arg(long, value_enum, default_value_t = ContextStrategy::DropOldest)]
+    context_strategy: ContextStrategy,
+    /// Estimated tokens the conversation may take up [default: the model's
+    /// context window less --max-tokens]
+    #[arg(long)]
+    context_budget: Option<usize>,
+    /// Sampling temperature, between 0.0 and 1.0
+    #[arg(long)]
+    temperature: Option<f32>,
+    /// Nucleus sampling probability mass, between 0.0 and 1.0
+    #[arg(long)]
+    top_p: Option<f32>,
+    /// Number of highest probability tokens considered
+    #[arg(long)]
+    top_k: Option<i32>,
+    /// Penalty on repeating what the answer already said, between 1.0 for
+    /// none and 2.0. The API may ignore it
+    #[arg(long, alias = "repeat-penalty")]
+    repetition_penalty: Option<f32>,
+    /// Enrich answers with web search results
+    #[arg(long)]
+    with_search_enhance: bool,
+    /// Answer from the documents of this knowledge base
+    #[arg(long)]
+    kb_id: Option<String>,
+    /// Ask for reproducible answers. Not documented by Baichuan, so it may be
+    /// ignored, and answers still vary unless --temperature is 0
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Ask for this many alternative answers and print them all, keeping the
+    /// first as context. Not documented by Baichuan, so fewer may come back
+    #[arg(long)]
+    candidates: Option<u32>,
+    /// Cut the answer off after this many tokens
+    #[arg(long)]
+    max_tokens: Option<u32>,
+    /// End the answer in front of this text. Can be repeated
+    #[arg(long, value_name = "TEXT")]
+    stop: Vec<String>,
+    /// Send a parameter that has no flag of its own, as KEY=VALUE. VALUE is
+    /// parsed as JSON, or sent as a string if it isn't JSON. Flags for the
+    /// same parameter win. Can be repeated
+    #[arg(long = "param", value_name = "KEY=VALUE", value_parser = parse_param)]
+    params: Vec<(String, serde_json::Value)>,
+    /// Ask for answers in JSON, and fail if a single prompt's answer isn't.
+    /// The prompt should still ask for JSON and describe it
+    #[arg(long)]
+    json_mode: bool,
+    /// When an answer is cut off by the length limit, ask for the rest up to
+    /// this many times and join the pieces into one answer
+    #[arg(long, value_name = "ROUNDS", num_args = 0..=1, require_equals = true, default_missing_value = "3")]
+    auto_continue: Option<usize>,
+    /// Send this single prompt, print the answer and exit
+    #[arg(short, long, conflicts_with = "stdin")]
+    prompt: Option<String>,
+    /// Send these words joined by spaces like --prompt, as in
+    /// `bc-cli "hello there"`
+    #[arg(value_name = "PROMPT", conflicts_with_all = ["prompt", "stdin", "template", "batch"])]
+    words: Vec<String>,
+    /// Read a single prompt from standard input, print the answer and exit
+    #[arg(long)]
+    stdin: bool,
+    /// Send the prompt in this file, print the answer and exit. Its
+    /// {{name}} placeholders are replaced by the --var values
+    #[arg(long, conflicts_with_all = ["prompt", "stdin"])]
+    template: Option<PathBuf>,
+    /// A value for the --template placeholders, as NAME=VALUE. Can be
+    /// repeated
+    #[arg(long = "var", value_name = "NAME=VALUE", requires = "template", value_parser = template::parse_var)]
+    vars: Vec<(String, String)>,
+    /// Answer each line of this file as a separate prompt and print the
+    /// results as a JSON array
+    #[arg(long, conflicts_with_all = ["prompt", "stdin"])]
+    batch: Option<PathBuf>,
+    /// Print each --batch result as a line of JSON as soon as it is
+    /// answered, rather than all of them in an array at the end. The
+    /// results come in the order they complete, with their line numbers
+    #[arg(long, requires = "batch")]
+    jsonl: bool,
+    /// How the prompt and the --batch lines are read
+    #[arg(long, value_enum, default_value_t = InputFormat::Text)]
+    input_format: InputFormat,
+    /// How many --batch requests are in flight at once
+    #[arg(long, default_value_t = 3)]
+    concurrency: usize,
+    /// How answers are printed
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    /// How text answers are formatted
+    #[arg(long, value_enum, default_value_t = Render::Plain)]
+    render: Render,
+    /// Wrap answers to this many columns, 0 not to wrap them [default: the
+    /// terminal width, or 0 if stdout isn't a terminal]
+    #[arg(long, value_name = "COLS")]
+    wrap: Option<usize>,
+    /// Print streamed answers a character at a time, as if being typed.
+    /// Ignored unless stdout is a terminal
+    #[arg(long)]
+    typewriter: bool,
+    /// Don't print token usage after each answer and at exit
+    #[arg(long)]
+    hide_usage: bool,
+    /// Print the count, min, max, mean, median and 95th percentile of the
+    /// response times when leaving the REPL
+    #[arg(long)]
+    stats: bool,
+    /// System prompt steering the assistant for the whole session. Sent as
+    /// a `system` message, which Baichuan documents for its OpenAI
+    /// compatible API only, so this API may ignore it
+    #[arg(long)]
+    system: Option<String>,
+    /// Read the system prompt from this file. With --system as well, the
+    /// file's contents follow it after a blank line
+    #[arg(long, value_name = "PATH")]
+    system_file: Option<PathBuf>,
+    /// Don't read or write the history of the lines entered in the REPL
+    #[arg(long, conflicts_with = "history_file")]
+    no_history: bool,
+    /// Keep the lines entered in the REPL in this file
+    /// [default: $XDG_STATE_HOME/baichuan_cli/history]
+    #[arg(long, value_name = "PATH")]
+    history_file: Option<PathBuf>,
+    /// Continue the conversation saved in this JSON file
+    #[arg(long)]
+    load_session: Option<PathBuf>,
+    /// Continue from this message of the loaded session on, counting from 0
+    #[arg(long, requires = "load_session")]
+    replay_since: Option<usize>,
+    /// Continue with the loaded session's messages before this one
+    #[arg(long, requires = "load_session")]
+    replay_until: Option<usize>,
+    /// Continue with only the last N turns of the loaded session, after
+    /// --replay-since and --replay-until
+    #[arg(long, requires = "load_session", value_name = "N")]
+    replay_last: Option<usize>,
+    /// Save the conversation to this JSON file on exit
+    #[arg(long)]
+    save_session: Option<PathBuf>,
+    /// Append every message to this file as it is sent or received, as text
+    /// or, with --output json, as JSON lines
+    #[arg(long)]
+    transcript: Option<PathBuf>,
+    /// Reuse the answers to identical requests stored in this directory,
+    /// and store new ones there
+    #[arg(long)]
+    cache: Option<PathBuf>,
+    /// Ignore --cache, sending every request
+    #[arg(long)]
+    no_cache: bool,
+    /// Seconds a cached answer is reused for
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    cache_ttl: u64,
+    /// Offer to resubmit a prompt rejected as unsafe, prefixed with a request
+    /// for a safe answer
+    #[arg(long)]
+    retry_on_safety: bool,
+    /// Like --retry-on-safety, but resubmit without asking
+    #[arg(long)]
+    auto_rephrase: bool,
+    /// Print the signed request for the prompt instead of sending it
+    #[arg(long, requires = "input")]
+    dry_run: bool,
+    /// Print an equivalent curl command for the prompt instead of sending
+    /// it. Its signature expires after a few minutes
+    #[arg(long, requires = "input")]
+    print_curl: bool,
+}
+
+#[derive(Args, Debug)]
+struct EmbedArgs {
+    /// Texts to embed, each into its own vector [default: the lines of
+    /// standard input]
+    texts: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Chat interactively, or answer a single prompt or a --batch file. This
+    /// is what runs without a subcommand
+    Chat(Box<ChatArgs>),
+    /// Print the embedding vectors of texts as a JSON array
+    Embed(EmbedArgs),
+    /// Check that the credentials are accepted by sending a minimal request,
+    /// exiting with a code telling why if they aren't (see the README)
+    Ping,
+    /// Print where the config file is read from, or the settings in effect
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// List the chat models that can be given to --model, with their context
+    /// windows and list prices, marking the selected one with *. The API has
+    /// no endpoint listing the models an account may use, so these are the
+    /// ones this version knows of
+    Models,
+    /// Work with the conversations saved by --save-session
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionAction {
+    /// Check that a file can be loaded with --load-session, printing how
+    /// many messages of each role it has, or where it fails to parse and
+    /// exiting with 1
+    Validate { path: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print where the config file is read from, whether or not it exists.
+    /// This is what runs without a subcommand
+    Path,
+    /// Print the settings in effect, merged from the flags, the config file
+    /// and the environment, in the format of the config file. Secrets are
+    /// redacted unless --show-secrets is given
+    Show,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+    // the chat settings when no subcommand is given
+    #[command(flatten)]
+    chat: ChatArgs,
+    #[command(subcommand)]
+    action: Option<Action>,
+}
+
+fn parse_key_pair(pair: &str) -> std::result::Result<KeyPair, String> {
+    let (api_key, secret_key) = pair
+        .split_once(':')
+        .ok_or_else(|| "expected API_KEY:SECRET_KEY".to_string())?;
+    Ok(KeyPair {
+        api_key: api_key.to_string(),
+        secret_key: secret_key.to_string(),
+    })
+}
+
+fn parse_param(param: &str) -> std::result::Result<(String, serde_json::Value), String> {
+    let (key, value) = param
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", param))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| value.into());
+    Ok((key.to_string(), value))
+}
+
+/// Fills in the settings from `config` that weren't given on the command line.
+fn apply_config(
+    global: &mut GlobalArgs,
+    args: &mut ChatArgs,
+    from_cli: impl Fn(&str) -> bool,
+    config: Config,
+) {
+    fn merge<T>(arg: &mut Option<T>, from_cli: bool, value: Option<T>) {
+        if !from_cli && value.is_some() {
+            *arg = value;
+        }
+    }
+    merge(&mut global.api_key, from_cli("api_key"), config.api_key);
+    merge(
+        &mut global.secret_key,
+        from_cli("secret_key"),
+        config.secret_key,
+    );
+    if !from_cli("extra_keys") && !config.extra_keys.is_empty() {
+        global.extra_keys = config.extra_keys;
+    }
+    if let (false, Some(model)) = (from_cli("model"), config.model) {
+        global.model = model;
+    }
+    let parameters = config.parameters;
+    merge(
+        &mut args.temperature,
+        from_cli("temperature"),
+        parameters.temperature,
+    );
+    merge(&mut args.top_p, from_cli("top_p"), parameters.top_p);
+    merge(&mut args.top_k, from_cli("top_k"), parameters.top_k);
+    merge(
+        &mut args.repetition_penalty,
+        from_cli("repetition_penalty"),
+        parameters.repetition_penalty,
+    );
+    merge(
+        &mut args.max_tokens,
+        from_cli("max_tokens"),
+        parameters.max_tokens,
+    );
+    if let (false, Some(stop)) = (from_cli("stop"), parameters.stop) {
+        args.stop = stop;
+    }
+    merge(&mut args.seed, from_cli("seed"), parameters.seed);
+    merge(&mut args.candidates, from_cli("candidates"), parameters.n);
+    merge(
+        &mut args.kb_id,
+        from_cli("kb_id"),
+        parameters.knowledge_base_id,
+    );
+    if !from_cli("json_mode") {
+        args.json_mode = parameters.response_format == Some(ResponseFormat::JsonObject);
+    }
+    if !from_cli("with_search_enhance") {
+        args.with_search_enhance = parameters.with_search_enhance.unwrap_or_default();
+    }
+    let mut extra = parameters.extra;
+    extra.extend(args.params.drain(..));
+    args.params = extra.into_iter().collect();
+}
+
+/// The sampling parameters set by the chat flags.
+fn chat_parameters(args: &ChatArgs) -> Parameters {
+    Parameters {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        top_k: args.top_k,
+        repetition_penalty: args.repetition_penalty,
+        with_search_enhance: args.with_search_enhance.then_some(true),
+        max_tokens: args.max_tokens,
+        stop: (!args.stop.is_empty()).then(|| args.stop.clone()),
+        seed: args.seed,
+        n: args.candidates,
+        knowledge_base_id: args.kb_id.clone(),
+        response_format: args.json_mode.then_some(ResponseFormat::JsonObject),
+        extra: args.params.iter().cloned().collect(),
+    }
+}
+
+/// The system prompt from --system and --system-file.
+fn system_prompt(args: &ChatArgs) -> std::result::Result<Option<String>, String> {
+    let Some(path) = &args.system_file else {
+        return Ok(args.system.clone());
+    };
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the system prompt {}: {}", path.display(), e))?;
+    let text = text.trim_end();
+    Ok(Some(match &args.system {
+        Some(system) => format!("{}\n\n{}", system, text),
+        None => text.to_string(),
+    }))
+}
+
+/// `config show`: the credentials, model and parameters in effect, as a
+/// config file would set them.
+fn show_config(global: &GlobalArgs, args: &ChatArgs, config_path: Option<&Path>) {
+    let redact = |key: KeyPair| {
+        if global.show_secrets {
+            key
+        } else {
+            key.redacted()
+        }
+    };
+    let key = redact(KeyPair {
+        api_key: global.api_key.clone().unwrap_or_default(),
+        secret_key: global.secret_key.clone().unwrap_or_default(),
+    });
+    let config = Config {
+        api_key: global.api_key.as_ref().map(|_| key.api_key),
+        secret_key: global.secret_key.as_ref().map(|_| key.secret_key),
+        model: Some(global.model),
+        extra_keys: global.extra_keys.iter().cloned().map(redact).collect(),
+        parameters: chat_parameters(args),
+    };
+    match config_path {
+        Some(path) if path.exists() => out::println(format_args!("# {}", path.display())),
+        Some(path) => out::println(format_args!("# {} (not found)", path.display())),
+        None => out::println("# (no config file)"),
+    }
+    match config.to_toml() {
+        Ok(text) => out::print(text),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Drops the oldest messages so that at most `max` remain, making sure the
+/// turns still start with a user message. A summary left by
+/// --context-strategy summarize is kept in front of them.
+fn trim_history(history: &mut Vec<ChatMessage>, max: usize) {
+    let summary = usize::from(history.first().is_some_and(context::is_summary));
+    if history.len() > max {
+        let end = (summary + history.len() - max).min(history.len());
+        history.drain(summary..end);
+    }
+    let leading = history[summary..]
+        .iter()
+        .take_while(|m| m.role != "user")
+        .count();
+    history.drain(summary..summary + leading);
+}
+
+/// Adds `message` to the transcript, if any. Failing to do so doesn't stop
+/// the conversation.
+fn record(transcript: &mut Option<Transcript>, message: &ChatMessage) {
+    if let Some(transcript) = transcript {
+        if let Err(e) = transcript.record(message) {
+            warn!("Failed to write the transcript: {}", e);
+        }
+    }
+}
+
+/// The outcome of one request: the answer as a single message, or `None` if
+/// the server sent nothing back, and the tokens it cost if it said.
+struct Turn {
+    answer: Option<ChatMessage>,
+    usage: Option<UsageInfo>,
+    /// Answered from the --cache, costing nothing.
+    cached: bool,
+}
+
+impl Turn {
+    /// The server reported success but sent no answer text.
+    fn is_empty(&self) -> bool {
+        self.answer.as_ref().is_none_or(|a| a.content.is_empty())
+    }
+
+    fn was_truncated(&self) -> bool {
+        self.answer.as_ref().is_some_and(ChatMessage::was_truncated)
+    }
+
+    fn from_resp(resp: BaichuanResp) -> Self {
+        Turn {
+            answer: resp.data.and_then(|data| data.messages.into_iter().next()),
+            usage: resp.usage,
+            cached: false,
+        }
+    }
+
+    /// The line with the tokens the turn cost, unless it was cached.
+    fn usage_line(&self) -> Option<String> {
+        match (&self.usage, self.cached) {
+            (_, true) => None,
+            (Some(usage), false) => Some(format!("[usage] {}", usage)),
+            (None, false) => Some(format!("[usage] {}", USAGE_UNAVAILABLE)),
+        }
+    }
+
+    /// Whether the answer is JSON, as asked for with --json-mode.
+    fn check_json(&self) -> std::result::Result<(), BaichuanError> {
+        match self.answer {
+            Some(_) => self.to_resp().answer_json::<serde_json::Value>().map(drop),
+            None => Ok(()),
+        }
+    }
+
+    /// The turn as the response it came from, for the cache.
+    fn to_resp(&self) -> BaichuanResp {
+        BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: self.answer.iter().cloned().collect(),
+            }),
+            usage: self.usage.clone(),
+        }
+    }
+}
+
+/// Streams the answer to `messages` to stdout as it arrives. Markdown can
+/// only be rendered as a whole, so it is printed once the stream has ended.
+///
+/// The answer is collected in `partial`, where what was received so far is
+/// left if the stream is dropped before it ends.
+async fn stream_answer(
+    client: &BaichuanClient,
+    messages: Vec<ChatMessage>,
+    render: Render,
+    show_role: bool,
+    partial: &mut Option<ChatMessage>,
+) -> std::result::Result<Turn, BaichuanError> {
+    let mut stream = Box::pin(client.stream(messages).await?);
+    // only what this stream adds is to be printed
+    let start = partial.as_ref().map_or(0, |p| p.content.len());
+    let answer = partial;
+    let mut usage = None;
+    let mut wrapper = Wrapper::new(wrap::width());
+    while let Some(event) = stream.next().await {
+        let message = match event? {
+            StreamEvent::Message(message) => message,
+            StreamEvent::Usage(u) => {
+                usage = Some(u);
+                continue;
+            }
+        };
+        let answer = answer.get_or_insert_with(|| {
+            if show_role {
+                out::print(style::role(&message.role));
+            }
+            ChatMessage::assistant("")
+        });
+        answer.content.push_str(&message.content);
+        if render == Render::Plain {
+            typewriter::print(&wrapper.push(&message.content)).await;
+        }
+        if message.finish_reason.is_some() {
+            answer.finish_reason = message.finish_reason;
+        }
+        // nobody reads the rest, so it isn't worth paying for
+        if out::closed() {
+            break;
+        }
+    }
+    match &answer {
+        Some(answer) if render == Render::Markdown => {
+            if show_role {
+                out::println("");
+            }
+            out::print(style::markdown(&answer.content[start..], wrap::width()));
+        }
+        Some(_) => out::println(wrapper.finish()),
+        None => {}
+    }
+    Ok(Turn {
+        answer: answer.take(),
+        usage,
+        cached: false,
+    })
+}
+
+/// Prints the complete API response as JSON and returns the answer.
+async fn json_answer(
+    client: &BaichuanClient,
+    messages: Vec<ChatMessage>,
+) -> std::result::Result<Turn, BaichuanError> {
+    let resp = client.send(messages).await?;
+    out::println(serde_json::to_string(&resp).map_err(BaichuanError::Serialization)?);
+    Ok(Turn::from_resp(resp))
+}
+
+/// Prints every answer of the response as a line of [`Output::Compact`].
+async fn compact_answer(
+    client: &BaichuanClient,
+    messages: Vec<ChatMessage>,
+) -> std::result::Result<Turn, BaichuanError> {
+    let resp = client.send(messages).await?;
+    print_compact(&resp);
+    Ok(Turn::from_resp(resp))
+}
+
+fn print_compact(resp: &BaichuanResp) {
+    for message in resp.data.iter().flat_map(|data| &data.messages) {
+        out::println(compact_line(message));
+    }
+}
+
+/// `message` as `role<TAB>content` without any line breaks or tabs in the
+/// content.
+fn compact_line(message: &ChatMessage) -> String {
+    let mut line = format!("{}\t", message.role);
+    for c in message.content.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c => line.push(c),
+        }
+    }
+    line
+}
+
+/// Prints a cached response the way a fresh one would have been, marked as
+/// cached. The returned turn has no usage since it cost nothing.
+fn print_cached(
+    resp: BaichuanResp,
+    output: Output,
+    render: Render,
+    show_role: bool,
+) -> std::result::Result<Turn, BaichuanError> {
+    if output == Output::Json {
+        let mut value = serde_json::to_value(&resp).map_err(BaichuanError::Serialization)?;
+        value["cached"] = true.into();
+        out::println(value);
+    }
+    if output == Output::Compact {
+        print_compact(&resp);
+    }
+    let turn = Turn::from_resp(resp);
+    if let (Output::Text, Some(answer)) = (output, &turn.answer) {
+        print_message(answer, render, show_role);
+        eprintln!("{}", style::dim("(cached)"));
+    }
+    Ok(Turn {
+        usage: None,
+        cached: true,
+        ..turn
+    })
+}
+
+/// Prints a complete answer the way [`stream_answer`] prints one as it
+/// arrives.
+fn print_message(message: &ChatMessage, render: Render, show_role: bool) {
+    if show_role {
+        out::print(style::role(&message.role));
+    }
+    match render {
+        Render::Plain => out::println(wrap::text(&message.content)),
+        Render::Markdown => {
+            if show_role {
+                out::println("");
+            }
+            out::print(style::markdown(&message.content, wrap::width()));
+        }
+    }
+}
+
+/// Prints every candidate answer, numbered. They are requested without
+/// streaming, as the streamed chunks can't be told apart by candidate.
+async fn candidates_answer(
+    client: &BaichuanClient,
+    messages: Vec<ChatMessage>,
+    render: Render,
+    show_role: bool,
+) -> std::result::Result<Turn, BaichuanError> {
+    let resp = client.send(messages).await?;
+    let candidates = resp.data.as_ref().map_or(&[][..], |data| &data.messages);
+    for (i, candidate) in candidates.iter().enumerate() {
+        if candidates.len() > 1 {
+            out::println(style::dim(format!(
+                "── candidate {} of {} ──",
+                i + 1,
+                candidates.len()
+            )));
+        }
+        print_message(candidate, render, show_role);
+    }
+    Ok(Turn::from_resp(resp))
+}
+
+async fn answer(
+    client: &BaichuanClient,
+    messages: Vec<ChatMessage>,
+    output: Output,
+    render: Render,
+    show_role: bool,
+    cache: Option<&Cache>,
+    partial: &mut Option<ChatMessage>,
+) -> std::result::Result<Turn, BaichuanError> {
+    let key = match cache {
+        Some(cache) => {
+            let key = Cache::key(&client.request_body(messages.clone())?);
+            if let Some(resp) = cache.get(&key) {
+                return print_cached(resp, output, render, show_role);
+            }
+            Some(key)
+        }
+        None => None,
+    };
+    let candidates = client.parameters().n.unwrap_or(1);
+    let turn = match output {
+        Output::Text if candidates > 1 => {
+            candidates_answer(client, messages, render, show_role).await?
+        }
+        Output::Text => stream_answer(client, messages, render, show_role, partial).await?,
+        Output::Json => json_answer(client, messages).await?,
+        Output::Compact => compact_answer(client, messages).await?,
+    };
+    if let (Some(cache), Some(key), false) = (cache, key, turn.is_empty()) {
+        if let Err(e) = cache.put(&key, &turn.to_resp()) {
+            warn!("Failed to cache the answer: {}", e);
+        }
+    }
+    Ok(turn)
+}
+
+/// Asked for the rest of an answer cut off by the length limit.
+const CONTINUE_PROMPT: &str =
+    "Continue exactly where your last answer stopped, without repeating any of it.";
+
+/// With `--auto-continue`, asks up to `rounds` times for the rest of a
+/// truncated answer to `messages`, printing each piece as it comes. The
+/// returned turn has the pieces joined and the usage of all the requests.
+async fn auto_continue(
+    client: &BaichuanClient,
+    messages: &[ChatMessage],
+    mut turn: Turn,
+    rounds: usize,
+    output: Output,
+    render: Render,
+    partial: &mut Option<ChatMessage>,
+) -> std::result::Result<Turn, BaichuanError> {
+    for round in 1..=rounds {
+        if !turn.was_truncated() || out::closed() {
+            break;
+        }
+        let Some(so_far) = turn.answer.take() else {
+            break;
+        };
+        eprintln!(
+            "{}",
+            style::dim(format!("(continuing, round {} of {})", round, rounds))
+        );
+        let mut conversation = messages.to_vec();
+        conversation.push(so_far.clone());
+        conversation.push(ChatMessage::user(CONTINUE_PROMPT));
+        // a streamed answer adds to `partial`, leaving the whole answer in
+        // the turn, while the other outputs only return the new piece
+        *partial = Some(so_far);
+        let next = answer(client, conversation, output, render, false, None, partial).await?;
+        let answer = match (partial.take(), next.answer) {
+            (Some(mut so_far), Some(piece)) => {
+                so_far.content.push_str(&piece.content);
+                so_far.finish_reason = piece.finish_reason;
+                so_far
+            }
+            (Some(so_far), None) => so_far,
+            (None, answer) => answer.unwrap_or_else(|| ChatMessage::assistant("")),
+        };
+        turn.answer = Some(answer);
+        turn.usage = match (turn.usage, next.usage) {
+            (Some(mut usage), Some(more)) => {
+                usage += &more;
+                Some(usage)
+            }
+            (usage, more) => usage.or(more),
+        };
+    }
+    Ok(turn)
+}
+
+/// Runs a slash command against the REPL state.
+fn run_command(
+    command: Command,
+    client: &mut BaichuanClient,
+    history: &mut Vec<ChatMessage>,
+    session_usage: &UsageInfo,
+) {
+    match command {
+        Command::Clear => {
+            history.clear();
+            out::println("Conversation cleared.");
+        }
+        Command::Help => out::println(commands::HELP),
+        Command::Model(None) => out::println(client.model()),
+        Command::Model(Some(model)) => {
+            client.set_model(model);
+            out::println(format_args!("Switched to {}.", model));
+        }
+        Command::Get(name) => match commands::get_parameter(client.parameters(), &name) {
+            Some(value) => out::println(format_args!("{} = {}", name, value)),
+            None => out::println(format_args!("{} is not set, the API default applies", name)),
+        },
+        Command::Set(name, value) => {
+            let mut parameters = client.parameters().clone();
+            match commands::set_parameter(&mut parameters, &name, &value) {
+                Ok(()) => {
+                    match commands::get_parameter(&parameters, &name) {
+                        Some(value) => out::println(format_args!("{} = {}", name, value)),
+                        None => out::println(format_args!(
+                            "{} is not set, the API default applies",
+                            name
+                        )),
+                    }
+                    client.set_parameters(parameters);
+                }
+                Err(e) => eprintln!("{}", style::error(e)),
+            }
+        }
+        Command::Params => {
+            for name in commands::PARAMETERS {
+                let value = commands::get_parameter(client.parameters(), name);
+                out::println(format_args!(
+                    "{:<20} {}",
+                    name,
+                    value.as_deref().unwrap_or("(default)")
+                ));
+            }
+        }
+        Command::Tokens => {
+            let tokens: usize = history.iter().map(|m| estimate_tokens(&m.content)).sum();
+            let model = client.model();
+            out::println(format_args!(
+                "~{} tokens, about ¥{:.4} with {} (estimated, the actual usage is reported after the answer)",
+                tokens,
+                model.estimate_cost(tokens),
+                model
+            ));
+        }
+        Command::Usage => out::println(session_usage),
+    }
+}
+
+/// Sends a one-token request to check that it is accepted, the error
+/// telling apart e.g. a bad key from an empty balance.
+async fn ping(client: BaichuanClient) -> std::result::Result<(), BaichuanError> {
+    let client = client.with_parameters(Parameters {
+        max_tokens: Some(1),
+        ..Parameters::default()
+    });
+    let started = Instant::now();
+    client.send(vec![ChatMessage::user("ping")]).await?;
+    out::println(format_args!(
+        "OK: {} answered in {}ms",
+        client.model(),
+        started.elapsed().as_millis()
+    ));
+    Ok(())
+}
+
+/// Reports the rejection of the unsafe `prompt` and, if the user agrees or
+/// `mode` is [`Rephrase::Auto`], rephrases it for resubmitting.
+fn resubmit_rephrased(err: &BaichuanError, prompt: &mut ChatMessage, mode: Rephrase) -> bool {
+    report_error(err);
+    if mode == Rephrase::Ask {
+        eprint!("Resubmit it asking for a safe answer? [y/N] ");
+        let mut reply = String::new();
+        if io::stdin().read_line(&mut reply).is_err() || !reply.trim().eq_ignore_ascii_case("y") {
+            return false;
+        }
+    }
+    safety::rephrase(prompt);
+    eprintln!(
+        "{}",
+        style::warning(format!(
+            "Resubmitting the prompt prefixed with {:?}",
+            safety::PREFIX.trim_end()
+        ))
+    );
+    true
+}
+
+/// Prints a request as it would go over the wire, headers sorted by name.
+fn print_dry_run(request: &SignedRequest, show_secrets: bool) {
+    out::println(format!("POST {}", request.url));
+    out::println(format_headers(&request.headers, show_secrets));
+    out::println("");
+    out::println(&request.body);
+}
+
+fn report_error(e: &BaichuanError) {
+    match e.resp_code() {
+        Some(code) if code.is_safety_violation() => {
+            let blocked = if code == RespCode::PromptNotSafe {
+                "prompt"
+            } else {
+                "answer"
+            };
+            let warning = format!("⚠️ This {} was blocked by the safety filter", blocked);
+            eprintln!("{}", style::warning(warning));
+        }
+        _ => {
+            let error = match e.request_id() {
+                Some(request_id) => format!("Failed (req {}): {}", request_id, e),
+                None => format!("Failed to request API: {}", e),
+            };
+            eprintln!("{}", style::error(error));
+        }
+    }
+}
+
+/// Prompts for the missing credentials without echoing them, then offers to
+/// save them to the config file so that they needn't be entered again.
+fn ask_credentials(
+    api_key: Option<String>,
+    secret_key: Option<String>,
+    config_path: Option<&Path>,
+) -> io::Result<(String, String)> {
+    eprintln!("No credentials given, enter them (see also --help and the config file)");
+    let api_key = match api_key {
+        Some(key) => key,
+        None => rpassword::prompt_password("API key: ")?,
+    };
+    let secret_key = match secret_key {
+        Some(key) => key,
+        None => rpassword::prompt_password("Secret key: ")?,
+    };
+    if let Some(path) = config_path {
+        eprint!("Save them to {}? [y/N] ", path.display());
+        let mut reply = String::new();
+        io::stdin().read_line(&mut reply)?;
+        if reply.trim().eq_ignore_ascii_case("y") {
+            match config::save_credentials(path, api_key.trim(), secret_key.trim()) {
+                Ok(()) => eprintln!("Saved."),
+                Err(e) => eprintln!("{}", style::error(e)),
+            }
+        }
+    }
+    Ok((api_key.trim().to_string(), secret_key.trim().to_string()))
+}
+
+/// Whether the argument `id` was given on the command line, before or after
+/// the subcommand name.
+fn from_cli(matches: &ArgMatches, id: &str) -> bool {
+    let given = |m: &ArgMatches| {
+        m.ids().any(|i| i.as_str() == id) && m.value_source(id) == Some(ValueSource::CommandLine)
+    };
+    given(matches) || matches.subcommand().is_some_and(|(_, sub)| given(sub))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-    Builder::new().filter_level(args.log_level).init();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let mut global = cli.global;
+    let (action, mut args) = match cli.action {
+        Some(Action::Chat(args)) => (None, *args),
+        action => {
+            // the top-level chat flags only apply without a subcommand
+            if let Some((name, _)) = matches.subcommand() {
+                let chat_args = ChatArgs::augment_args(clap::Command::new("chat"));
+                let stray = chat_args.get_arguments().find(|arg| {
+                    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+                });
+                if let Some(arg) = stray {
+                    clap::Error::raw(
+                        clap::error::ErrorKind::ArgumentConflict,
+                        format!(
+                            "--{} can't be used with the {} subcommand\n",
+                            arg.get_long().unwrap_or_default(),
+                            name
+                        ),
+                    )
+                    .exit();
+                }
+            }
+            (action, cli.chat)
+        }
+    };
+    let mut targets = Targets::new().with_default(global.log_level);
+    if global.verbose {
+        targets = targets.with_target(WIRE_TARGET, LevelFilter::TRACE);
+        set_wire_secrets(global.show_secrets);
+    }
+    let ansi = io::stderr().is_terminal() && !global.no_color && env::var_os("NO_COLOR").is_none();
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(io::stderr).with_ansi(ansi))
+        .with(targets)
+        .init();
+    if global.no_color {
+        style::disable();
+    }
+
+    if let Some(Action::Session {
+        action: SessionAction::Validate { path },
+    }) = &action
+    {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        match session::validate(&text) {
+            Ok(summary) => out::println(format_args!("{}: {}", path.display(), summary)),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("{}: {}", path.display(), e)));
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let config_path = global.config.clone().or_else(config::default_path);
+    if let Some(Action::Config {
+        action: None | Some(ConfigAction::Path),
+    }) = action
+    {
+        match &config_path {
+            Some(path) if path.exists() => out::println(path.display()),
+            Some(path) => out::println(format_args!("{} (not found)", path.display())),
+            None => out::println("(no config file location, set HOME or XDG_CONFIG_HOME)"),
+        }
+        return Ok(());
+    }
+    if let Some(path) = &config_path {
+        match Config::load(path, global.config.is_some()) {
+            Ok(config) => apply_config(&mut global, &mut args, |id| from_cli(&matches, id), config),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(Action::Config {
+        action: Some(ConfigAction::Show),
+    }) = action
+    {
+        show_config(&global, &args, config_path.as_deref());
+        return Ok(());
+    }
+    if let Some(Action::Models) = action {
+        for model in Model::value_variants() {
+            let selected = if *model == global.model { " *" } else { "" };
+            out::println(format_args!(
+                "{:<22}context={:<8}price={} CNY/1k tokens{}",
+                model.to_string(),
+                model.context_window(),
+                model.price_per_1k_tokens(),
+                selected
+            ));
+        }
+        return Ok(());
+    }
+    if global.mock {
+        eprintln!(
+            "{}",
+            style::warning("--mock: answers are made up locally, nothing is sent to the API")
+        );
+    }
+    let (api_key, secret_key) = match (global.api_key.clone(), global.secret_key.clone()) {
+        (Some(api_key), Some(secret_key)) => (api_key, secret_key),
+        (api_key, secret_key) if global.mock => (
+            api_key.unwrap_or_else(|| "mock".to_string()),
+            secret_key.unwrap_or_else(|| "mock".to_string()),
+        ),
+        (api_key, secret_key) if io::stdin().is_terminal() => {
+            match ask_credentials(api_key, secret_key, config_path.as_deref()) {
+                Ok(keys) => keys,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(exit::AUTH);
+                }
+            }
+        }
+        _ => {
+            eprintln!("An api key and a secret key are required, via --api-key/--secret-key, the config file or API_KEY/SECRET_KEY");
+            std::process::exit(exit::AUTH);
+        }
+    };
+    let keys = std::iter::once((api_key.as_str(), secret_key.as_str())).chain(
+        global
+            .extra_keys
+            .iter()
+            .map(|key| (key.api_key.as_str(), key.secret_key.as_str())),
+    );
+    for (api_key, secret_key) in keys {
+        if let Err(e) = validate_credentials(api_key, secret_key) {
+            eprintln!("{}", e);
+            std::process::exit(exit::code(&e));
+        }
+    }
+    if let Err(e) = validate_base_url(&global.base_url) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    let retry_policy = RetryPolicy {
+        max_retries: global.max_retries,
+        ..RetryPolicy::default()
+    };
+    let system = match system_prompt(&args) {
+        Ok(system) => system,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let parameters = chat_parameters(&args);
+    if let Err(e) = parameters.validate() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    let http_options = HttpOptions {
+        timeout: (global.timeout_secs > 0).then(|| Duration::from_secs(global.timeout_secs)),
+        connect_timeout: Duration::from_secs(global.connect_timeout),
+        read_timeout: global.read_timeout.map(Duration::from_secs),
+        proxy: global.proxy,
+        user_agent: global.user_agent,
+        danger_accept_invalid_certs: global.danger_accept_invalid_certs,
+        keepalive: global.keepalive.map(Duration::from_secs),
+    };
+    if global.danger_accept_invalid_certs {
+        eprintln!(
+            "{}",
+            style::warning(
+                "⚠️ --danger-accept-invalid-certs: TLS certificates are not verified, \
+                 the connection to the API may be intercepted"
+            )
+        );
+    }
+    let http = match http_client(&http_options) {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Failed to build HTTP client: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut client = BaichuanClient::from_http(http, api_key, secret_key)
+        .with_model(global.model)
+        .with_fallback_models(global.fallback_models)
+        .with_parameters(parameters)
+        .with_retry_policy(retry_policy)
+        .with_system_prompt(system)
+        .with_sign_algo(global.sign_algo)
+        .with_base_url(global.base_url)
+        .with_slow_request_threshold(
+            (global.slow_request_secs > 0).then(|| Duration::from_secs(global.slow_request_secs)),
+        );
+    for key in global.extra_keys {
+        client = client.with_additional_key(key.api_key, key.secret_key);
+    }
+    if global.rpm > 0 {
+        client = client.with_rate_limit(global.rpm);
+    }
+    if global.mock {
+        client = client.with_mock_transport();
+    }
+    if !global.no_clock_check && !args.dry_run && !args.print_curl {
+        if let Err(e) = client.check_clock().await {
+            debug!("Failed to check the clock: {}", e);
+        }
+    }
+
+    match action {
+        Some(Action::Ping) => {
+            if let Err(e) = ping(client).await {
+                report_error(&e);
+                std::process::exit(exit::code(&e));
+            }
+            Ok(())
+        }
+        Some(Action::Embed(embed_args)) => {
+            if let Err(e) = embed(&client, embed_args).await {
+                report_error(&e);
+                std::process::exit(exit::code(&e));
+            }
+            Ok(())
+        }
+        _ => chat(client, args, global.show_secrets).await,
+    }
+}
+
+/// Prints the embeddings of the texts, in requests of at most
+/// [`MAX_EMBEDDING_INPUTS`] texts.
+async fn embed(client: &BaichuanClient, args: EmbedArgs) -> std::result::Result<(), BaichuanError> {
+    let texts = if args.texts.is_empty() {
+        io::read_to_string(io::stdin())
+            .map_err(BaichuanError::Io)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    } else {
+        args.texts
+    };
+    let mut vectors = vec![];
+    for chunk in texts.chunks(MAX_EMBEDDING_INPUTS) {
+        vectors.extend(client.embed(chunk.to_vec()).await?);
+    }
+    out::println(serde_json::Value::from(vectors));
+    Ok(())
+}
+
+/// The `chat` subcommand: a single prompt, a batch file or the REPL.
+async fn chat(mut client: BaichuanClient, args: ChatArgs, show_secrets: bool) -> Result<()> {
+    wrap::init(args.wrap);
+    typewriter::init(args.typewriter);
+    if let Some(path) = &args.batch {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let conversations = match batch::conversations(&text, args.input_format) {
+            Ok(conversations) => conversations,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        if args.jsonl {
+            let mut results = Box::pin(batch::results(&client, conversations, args.concurrency));
+            while let Some(result) = results.next().await {
+                match serde_json::to_string(&result) {
+                    Ok(json) => out::println(json),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+                if out::closed() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let results = batch::run(&client, conversations, args.concurrency).await;
+        match serde_json::to_string_pretty(&results) {
+            Ok(json) => out::println(json),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let rephrase = match (args.auto_rephrase, args.retry_on_safety) {
+        (true, _) => Rephrase::Auto,
+        (false, true) => Rephrase::Ask,
+        (false, false) => Rephrase::Off,
+    };
+    let cache = match (&args.cache, args.no_cache) {
+        (Some(dir), false) => Some(Cache::new(dir.clone(), Duration::from_secs(args.cache_ttl))),
+        _ => None,
+    };
+    let mut transcript = match &args.transcript {
+        Some(path) => match Transcript::open(path, args.output == Output::Json) {
+            Ok(transcript) => Some(transcript),
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let words = (!args.words.is_empty()).then(|| args.words.join(" "));
+    let prompt = match (args.prompt.or(words), args.stdin, &args.template) {
+        (Some(prompt), _, _) => Some(prompt),
+        (None, true, _) => Some(io::read_to_string(io::stdin())?),
+        (None, false, Some(path)) => {
+            let text = match std::fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", path.display(), e);
+                    std::process::exit(2);
+                }
+            };
+            match template::render(&text, &args.vars.iter().cloned().collect()) {
+                Ok(prompt) => Some(prompt),
+                Err(e) => {
+                    eprintln!("Failed to fill in {}: {}", path.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        (None, false, None) => None,
+    };
+    // usage is part of the JSON output already
+    let show_usage = !args.hide_usage && args.output != Output::Json;
+    if let Some(prompt) = prompt {
+        let mut messages = match input::conversation(prompt, args.input_format) {
+            Ok(messages) => messages,
+            Err(e) => {
+                eprintln!("Failed to read the prompt: {}", e);
+                std::process::exit(2);
+            }
+        };
+        let budget = context::budget(&client, args.context_budget);
+        let fitted = match args.context_strategy {
+            // nothing is to be sent when only printing the request
+            ContextStrategy::Summarize if args.dry_run || args.print_curl => {
+                context::fit(&mut messages, budget, ContextStrategy::DropOldest)
+            }
+            ContextStrategy::Summarize => {
+                let window = client.model().context_window();
+                context::summarize(&mut messages, budget, window, |messages| {
+                    client.send_plain(messages)
+                })
+                .await
+                .map(|_| 0)
+            }
+            strategy => context::fit(&mut messages, budget, strategy),
+        };
+        if let Err(e) = fitted {
+            eprintln!("{}", style::error(format!("Not sent: {}", e)));
+            std::process::exit(1);
+        }
+        if args.dry_run || args.print_curl {
+            let request = match args.output {
+                Output::Text => client.prepare_stream(messages),
+                Output::Json | Output::Compact => client.prepare(messages),
+            };
+            match request {
+                Ok(request) if args.print_curl => out::println(request.to_curl(show_secrets)),
+                Ok(request) => print_dry_run(&request, show_secrets),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        for message in &messages {
+            record(&mut transcript, message);
+        }
+        let result = loop {
+            let mut partial = None;
+            let result = async {
+                let turn = answer(
+                    &client,
+                    messages.clone(),
+                    args.output,
+                    args.render,
+                    false,
+                    cache.as_ref(),
+                    &mut partial,
+                )
+                .await?;
+                let rounds = args.auto_continue.unwrap_or(0);
+                auto_continue(
+                    &client,
+                    &messages,
+                    turn,
+                    rounds,
+                    args.output,
+                    args.render,
+                    &mut partial,
+                )
+                .await
+            }
+            .await;
+            match (&result, messages.last_mut()) {
+                (Err(e), Some(prompt))
+                    if rephrase != Rephrase::Off && safety::can_rephrase(e, Some(prompt)) =>
+                {
+                    if !resubmit_rephrased(e, prompt, rephrase) {
+                        std::process::exit(exit::code(e));
+                    }
+                    record(&mut transcript, prompt);
+                }
+                _ => break result,
+            }
+        };
+        match result {
+            Ok(turn) => {
+                if let Some(answer) = &turn.answer {
+                    record(&mut transcript, answer);
+                }
+                if turn.is_empty() {
+                    eprintln!("{}", style::warning("(empty response)"));
+                }
+                if turn.was_truncated() {
+                    eprintln!(
+                        "{}",
+                        style::warning("⚠️ The answer was cut off by the length limit")
+                    );
+                }
+                if let (true, Err(e)) = (args.json_mode, turn.check_json()) {
+                    eprintln!("{}", style::error(&e));
+                    std::process::exit(exit::code(&e));
+                }
+                if let (true, Some(line)) = (show_usage, turn.usage_line()) {
+                    eprintln!("{}", style::dim(line));
+                }
+            }
+            Err(e) => {
+                report_error(&e);
+                std::process::exit(exit::code(&e));
+            }
+        }
+        return Ok(());
+    }
 
     let mut rl = DefaultEditor::new()?;
-    if rl.load_history(".bc_cli_history").is_err() {
-        debug!("No previous history loaded.");
+    let history_file = if args.no_history {
+        None
+    } else {
+        args.history_file.clone().or_else(history::default_path)
+    };
+    if let Some(path) = &history_file {
+        let from = match args.history_file {
+            Some(_) => path.as_path(),
+            None => history::load_path(path),
+        };
+        if rl.load_history(from).is_err() {
+            debug!("No previous history loaded from {}.", from.display());
+        }
     }
-    loop {
-        let readline = rl.readline("❯ ");
+    let mut history: Vec<ChatMessage> = match &args.load_session {
+        Some(path) => match load_session(path) {
+            Ok(Some(messages)) => {
+                info!("Loaded {} messages from {}", messages.len(), path.display());
+                let replay = Replay {
+                    since: args.replay_since,
+                    until: args.replay_until,
+                    last: args.replay_last,
+                };
+                if replay.is_empty() {
+                    messages
+                } else {
+                    match replay.slice(messages) {
+                        Ok(messages) => {
+                            info!("Replaying {} of them", messages.len());
+                            messages
+                        }
+                        Err(e) => {
+                            eprintln!("{}", style::error(e));
+                            std::process::exit(2);
+                        }
+                    }
+                }
+            }
+            Ok(None) => {
+                debug!("No session at {}, starting fresh.", path.display());
+                vec![]
+            }
+            Err(e) => {
+                eprintln!("Failed to load session {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => vec![],
+    };
+    let mut session_usage = UsageInfo::default();
+    // answers whose usage the server didn't report, left out of the total
+    let mut unreported = 0;
+    let mut latencies = LatencyStats::default();
+    let prompt = style::prompt();
+    'repl: loop {
+        // the answers can't be shown anymore
+        if out::closed() {
+            break;
+        }
+        let readline = rl.readline(&prompt);
         match readline {
             Ok(line) => {
-                let r =
-                    make_baichuan_request(&args.api_key, &args.secret_key, args.model, vec![line])
-                        .await;
-                match r {
-                    Ok(resp) => {
-                        if let Some(data) = resp.data {
-                            data.messages.iter().for_each(|message| {
-                                println!("[{}]: {}", message.role, message.content)
-                            })
+                // nothing to ask, so nothing is sent
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if commands::is_cancelled(&line, &args.cancel_keyword) {
+                    eprintln!("{}", style::dim("(discarded)"));
+                    continue;
+                }
+                rl.add_history_entry(line.as_str())?;
+                match Command::parse(&line) {
+                    Some(Ok(command)) => {
+                        run_command(command, &mut client, &mut history, &session_usage);
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        eprintln!("{}", style::error(e));
+                        continue;
+                    }
+                    None => {}
+                }
+                history.push(ChatMessage::user(line));
+                trim_history(&mut history, args.max_history);
+                let budget = context::budget(&client, args.context_budget);
+                let fitted = match args.context_strategy {
+                    ContextStrategy::Summarize => {
+                        let window = client.model().context_window();
+                        context::summarize(&mut history, budget, window, |messages| {
+                            client.send_plain(messages)
+                        })
+                        .await
+                    }
+                    strategy => {
+                        context::fit(&mut history, budget, strategy).map(context::Fitted::dropped)
+                    }
+                };
+                if let Some(usage) = fitted.as_ref().ok().and_then(|f| f.usage.as_ref()) {
+                    session_usage += usage;
+                }
+                match fitted {
+                    Ok(fitted) if fitted.messages == 0 => {}
+                    Ok(fitted) => eprintln!(
+                        "{}",
+                        style::dim(format!(
+                            "{} the {} oldest messages to fit the context",
+                            fitted.verb(),
+                            fitted.messages
+                        ))
+                    ),
+                    Err(e) => {
+                        history.pop();
+                        eprintln!(
+                            "{}",
+                            style::error(format!("Not sent: {}, /clear to start over", e))
+                        );
+                        continue;
+                    }
+                }
+                if let Some(message) = history.last() {
+                    record(&mut transcript, message);
+                }
+                let turn = loop {
+                    let throttle = client.throttle_time();
+                    if !throttle.is_zero() {
+                        eprintln!(
+                            "{}",
+                            style::dim(format!(
+                                "throttling for {}s",
+                                throttle.as_secs_f32().ceil()
+                            ))
+                        );
+                    }
+                    // Ctrl-C while waiting drops the request. The part of the
+                    // answer already received is kept as an answer marked
+                    // cancelled, and without any the question goes too.
+                    let mut partial = None;
+                    let started = Instant::now();
+                    let turn = tokio::select! {
+                        turn = async {
+                            let turn = answer(
+                                &client,
+                                history.clone(),
+                                args.output,
+                                args.render,
+                                true,
+                                cache.as_ref(),
+                                &mut partial,
+                            )
+                            .await?;
+                            let rounds = args.auto_continue.unwrap_or(0);
+                            auto_continue(
+                                &client,
+                                &history,
+                                turn,
+                                rounds,
+                                args.output,
+                                args.render,
+                                &mut partial,
+                            )
+                            .await
+                        } => turn,
+                        _ = tokio::signal::ctrl_c() => {
+                            match partial.filter(|p: &ChatMessage| !p.content.is_empty()) {
+                                Some(mut answer) => {
+                                    if args.render == Render::Markdown {
+                                        out::print(style::markdown(&answer.content, wrap::width()));
+                                    }
+                                    out::println("");
+                                    eprintln!("{}", style::warning("Request cancelled, the partial answer was kept."));
+                                    answer.cancel();
+                                    record(&mut transcript, &answer);
+                                    history.push(answer);
+                                }
+                                None => {
+                                    history.pop();
+                                    out::println("");
+                                    eprintln!("{}", style::warning("Request cancelled."));
+                                }
+                            }
+                            continue 'repl;
                         }
+                    };
+                    if turn.is_ok() {
+                        latencies.record(started.elapsed());
                     }
+                    match (&turn, history.last_mut()) {
+                        (Err(e), Some(prompt))
+                            if rephrase != Rephrase::Off
+                                && safety::can_rephrase(e, Some(prompt)) =>
+                        {
+                            if !resubmit_rephrased(e, prompt, rephrase) {
+                                history.pop();
+                                continue 'repl;
+                            }
+                            record(&mut transcript, prompt);
+                        }
+                        _ => break turn,
+                    }
+                };
+                match turn {
+                    Ok(turn) => {
+                        if turn.was_truncated() {
+                            eprintln!(
+                                "{}",
+                                style::warning("⚠️ The answer was cut off by the length limit, send \"continue\" for the rest")
+                            );
+                        }
+                        if let (true, Err(e)) = (args.json_mode, turn.check_json()) {
+                            eprintln!("{}", style::warning(format!("⚠️ {}", e)));
+                        }
+                        match &turn.usage {
+                            Some(usage) => session_usage += usage,
+                            None if !turn.cached => unreported += 1,
+                            None => {}
+                        }
+                        if let (true, Some(line)) = (show_usage, turn.usage_line()) {
+                            eprintln!("{}", style::dim(line));
+                        }
+                        match turn.answer {
+                            Some(answer) if !answer.content.is_empty() => {
+                                record(&mut transcript, &answer);
+                                history.push(answer);
+                            }
+                            // nothing to answer, so don't keep the question either
+                            _ => {
+                                history.pop();
+                                eprintln!("{}", style::warning("(empty response)"));
+                            }
+                        }
+                    }
+                    // blocked or failed turns are not kept as context
                     Err(e) => {
-                        eprintln!("Failed to request API: {}", e)
+                        history.pop();
+                        report_error(&e);
                     }
                 }
             }
@@ -60,8 +1780,70 @@ async fn main() -> Result<()> {
             }
         }
     }
-    if rl.save_history(".bc_cli_history").is_err() {
-        error!("Could not save history.");
+    if show_usage {
+        let mut line = format!("[usage] session {}", session_usage);
+        if unreported > 0 {
+            line.push_str(&format!(" ({} answers without usage)", unreported));
+        }
+        eprintln!("{}", style::dim(line));
+    }
+    if args.stats {
+        eprintln!("{}", style::dim(format!("[latency] {}", latencies)));
+    }
+    if let Some(path) = &args.save_session {
+        if let Err(e) = save_session(path, &history) {
+            error!("Could not save session to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(path) = &history_file {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = rl.save_history(path) {
+            error!("Could not save history to {}: {}", path.display(), e);
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_positional_prompt_is_input() {
+        let cli = Cli::try_parse_from(["bc-cli", "--dry-run", "hello", "there"]).unwrap();
+        assert!(cli.chat.dry_run);
+        assert_eq!(cli.chat.words, ["hello", "there"]);
+        assert!(Cli::try_parse_from(["bc-cli", "--print-curl", "hello"]).is_ok());
+        assert!(Cli::try_parse_from(["bc-cli", "--dry-run"]).is_err());
+        assert!(Cli::try_parse_from(["bc-cli", "-p", "hi", "hello"]).is_err());
+    }
+
+    #[test]
+    fn test_trim_history_keeps_summary() {
+        let summary = ChatMessage::system(format!("{}早先的对话", context::SUMMARY_PREFIX));
+        let mut history = vec![
+            summary.clone(),
+            ChatMessage::user("第一个问题"),
+            ChatMessage::assistant("第一个回答"),
+            ChatMessage::user("第二个问题"),
+            ChatMessage::assistant("第二个回答"),
+            ChatMessage::user("第三个问题"),
+        ];
+        trim_history(&mut history, 4);
+        let contents: Vec<&str> = history.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                summary.content.as_str(),
+                "第二个问题",
+                "第二个回答",
+                "第三个问题"
+            ]
+        );
+        trim_history(&mut history, 3);
+        let contents: Vec<&str> = history.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, [summary.content.as_str(), "第三个问题"]);
+    }
+}
@@ -0,0 +1,39 @@
+//! Writing answers to stdout, which the reader may close before they end,
+//! as `head` does once it has the lines it wants.
+
+use std::fmt::Display;
+use std::io::{self, ErrorKind, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Writes `text` to stdout and flushes it, so that a partial line shows.
+/// A closed stdout is no error: the reader has all it wanted, and the rest
+/// of the output is dropped, see [`closed`].
+///
+/// # Panics
+///
+/// Like `print!`, if writing fails otherwise.
+pub fn print(text: impl Display) {
+    let mut stdout = io::stdout().lock();
+    check(write!(stdout, "{}", text).and_then(|()| stdout.flush()));
+}
+
+/// Like [`print()`], with a newline after `text`.
+pub fn println(text: impl Display) {
+    check(writeln!(io::stdout().lock(), "{}", text));
+}
+
+/// Whether a write found stdout closed, so that there is no point in
+/// producing more output.
+pub fn closed() -> bool {
+    CLOSED.load(Ordering::Relaxed)
+}
+
+fn check(written: io::Result<()>) {
+    match written {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => CLOSED.store(true, Ordering::Relaxed),
+        Err(e) => panic!("failed printing to stdout: {}", e),
+        Ok(()) => {}
+    }
+}
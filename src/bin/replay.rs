@@ -0,0 +1,121 @@
+//! `--replay-since`, `--replay-until` and `--replay-last`: continuing with
+//! only part of a loaded session.
+
+use baichuan_cli::ChatMessage;
+
+/// Which part of a loaded session to continue with.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Replay {
+    /// Index of the first message kept
+    pub since: Option<usize>,
+    /// Index of the first message dropped after the kept ones
+    pub until: Option<usize>,
+    /// Number of turns kept at the end, a turn being a user message and the
+    /// answers to it
+    pub last: Option<usize>,
+}
+
+impl Replay {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.until.is_none() && self.last.is_none()
+    }
+
+    /// Keeps the messages from `since` up to `until`, then the last turns of
+    /// those. The result starts with a user message, as the API requires.
+    pub fn slice(&self, mut messages: Vec<ChatMessage>) -> Result<Vec<ChatMessage>, String> {
+        let since = self.since.unwrap_or(0);
+        let until = self.until.unwrap_or(messages.len()).min(messages.len());
+        if since > until {
+            return Err(format!(
+                "--replay-since {} is past --replay-until {} or the {} messages of the session",
+                since,
+                until,
+                messages.len()
+            ));
+        }
+        messages.truncate(until);
+        messages.drain(..since);
+        if let Some(last) = self.last {
+            let starts: Vec<usize> = messages
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| m.role == "user")
+                .map(|(i, _)| i)
+                .collect();
+            let start = match starts.len().checked_sub(last) {
+                Some(skipped) if last > 0 => starts[skipped],
+                Some(_) => messages.len(),
+                None => 0,
+            };
+            messages.drain(..start);
+        }
+        let leading = messages.iter().take_while(|m| m.role != "user").count();
+        messages.drain(..leading);
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::user("1"),
+            ChatMessage::assistant("a"),
+            ChatMessage::user("2"),
+            ChatMessage::assistant("b"),
+            ChatMessage::user("3"),
+            ChatMessage::assistant("c"),
+        ]
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_replay_last() {
+        let replay = Replay {
+            last: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            contents(&replay.slice(session()).unwrap()),
+            ["2", "b", "3", "c"]
+        );
+        let replay = Replay {
+            last: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(replay.slice(session()).unwrap().len(), 6);
+        let replay = Replay {
+            last: Some(0),
+            ..Default::default()
+        };
+        assert!(replay.slice(session()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replay_range() {
+        // Starting at an answer drops it, since the API wants a user message
+        // first.
+        let replay = Replay {
+            since: Some(1),
+            until: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(contents(&replay.slice(session()).unwrap()), ["2", "b"]);
+        let replay = Replay {
+            since: Some(2),
+            until: Some(100),
+            last: Some(1),
+        };
+        assert_eq!(contents(&replay.slice(session()).unwrap()), ["3", "c"]);
+        let replay = Replay {
+            since: Some(7),
+            ..Default::default()
+        };
+        assert!(replay.slice(session()).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! `--retry-on-safety`: resubmitting prompts rejected as unsafe.
+
+use baichuan_cli::{BaichuanError, ChatMessage, RespCode};
+
+/// Put ahead of a rejected prompt, asking for a safe and factual answer.
+/// Prompts are often rejected over a single sensitive word, and the framing
+/// can be enough to have them accepted.
+pub const PREFIX: &str = "请以安全、合规、客观的方式回答以下问题：\n\n";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rephrase {
+    Off,
+    /// Ask before resubmitting
+    Ask,
+    /// Resubmit right away
+    Auto,
+}
+
+/// Whether `prompt` was rejected as unsafe and hasn't been rephrased yet, so
+/// that every prompt is resubmitted at most once.
+pub fn can_rephrase(err: &BaichuanError, prompt: Option<&ChatMessage>) -> bool {
+    err.resp_code() == Some(RespCode::PromptNotSafe)
+        && prompt.is_some_and(|p| p.role == "user" && !p.content.starts_with(PREFIX))
+}
+
+pub fn rephrase(prompt: &mut ChatMessage) {
+    prompt.content.insert_str(0, PREFIX);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rephrase_once() {
+        let err = BaichuanError::Api {
+            code: RespCode::PromptNotSafe,
+            msg: "Topic violates security policy".to_string(),
+            request_id: None,
+        };
+        let mut prompt = ChatMessage::user("你好");
+        assert!(can_rephrase(&err, Some(&prompt)));
+        rephrase(&mut prompt);
+        assert_eq!(prompt.content, format!("{}你好", PREFIX));
+        assert!(!can_rephrase(&err, Some(&prompt)));
+        let err = BaichuanError::Api {
+            code: RespCode::AnswerNotSafe,
+            msg: "Topic violates security policy".to_string(),
+            request_id: None,
+        };
+        assert!(!can_rephrase(&err, Some(&ChatMessage::user("你好"))));
+    }
+}
@@ -0,0 +1,93 @@
+//! `session validate`: checking a file for --load-session before using it.
+
+use baichuan_cli::ChatMessage;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What a valid session file holds.
+#[derive(Debug)]
+pub struct Summary {
+    pub messages: usize,
+    /// How many messages each role has, by role name.
+    pub roles: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = if self.messages == 1 { "" } else { "s" };
+        write!(f, "{} message{}", self.messages, plural)?;
+        let roles: Vec<String> = self
+            .roles
+            .iter()
+            .map(|(role, count)| format!("{} {}", count, role))
+            .collect();
+        if !roles.is_empty() {
+            write!(f, ": {}", roles.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `text` as a saved session. The error points at the line and
+/// column where parsing failed, quoting the line.
+pub fn validate(text: &str) -> Result<Summary, String> {
+    let messages: Vec<ChatMessage> = serde_json::from_str(text).map_err(|e| {
+        let line = text.lines().nth(e.line().saturating_sub(1)).unwrap_or("");
+        let caret = " ".repeat(line.chars().take(e.column().saturating_sub(1)).count());
+        let position = format!(" at line {} column {}", e.line(), e.column());
+        let message = e.to_string();
+        let message = message.strip_suffix(&position).unwrap_or(&message);
+        format!(
+            "line {}, column {}: {}\n{}\n{}^",
+            e.line(),
+            e.column(),
+            message,
+            line,
+            caret
+        )
+    })?;
+    let mut roles = BTreeMap::new();
+    for message in &messages {
+        *roles.entry(message.role.clone()).or_insert(0) += 1;
+    }
+    Ok(Summary {
+        messages: messages.len(),
+        roles,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let text = r#"[
+  {"role": "user", "content": "你好"},
+  {"role": "assistant", "content": "你好！"},
+  {"role": "user", "content": "再见"}
+]"#;
+        assert_eq!(
+            validate(text).unwrap().to_string(),
+            "3 messages: 1 assistant, 2 user"
+        );
+        assert_eq!(validate("[]").unwrap().to_string(), "0 messages");
+        let text = r#"[{"role": "system", "content": "简洁"}]"#;
+        assert_eq!(validate(text).unwrap().to_string(), "1 message: 1 system");
+    }
+
+    #[test]
+    fn test_validate_points_at_error() {
+        let text = "[\n  {\"role\": \"user\", \"content\": \"hi\"},\n  {\"role\": \"user\" \"content\": \"ho\"}\n]";
+        let err = validate(text).unwrap_err();
+        assert!(
+            err.starts_with("line 3, column 19: expected `,` or `}`\n"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("\n  {\"role\": \"user\" \"content\": \"ho\"}\n                  ^"));
+        assert!(validate(r#"[{"role": "user"}]"#)
+            .unwrap_err()
+            .contains("missing field `content`"));
+    }
+}
@@ -0,0 +1,64 @@
+//! `--stats`: how long the requests of a session took.
+
+use std::fmt;
+use std::time::Duration;
+
+/// The latencies of the answered requests, each from sending the request
+/// until the whole answer was received.
+#[derive(Default, Debug)]
+pub struct LatencyStats {
+    samples: Vec<Duration>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    /// The latency that `percent` of the requests didn't exceed, by the
+    /// nearest-rank method.
+    fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+        let rank = (sorted.len() * percent).div_ceil(100).max(1);
+        sorted[rank - 1]
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.samples.is_empty() {
+            return write!(f, "requests=0");
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+        let secs = |d: Duration| format!("{:.2}s", d.as_secs_f64());
+        write!(
+            f,
+            "requests={} min={} max={} mean={} p50={} p95={}",
+            sorted.len(),
+            secs(sorted[0]),
+            secs(sorted[sorted.len() - 1]),
+            secs(total / sorted.len() as u32),
+            secs(Self::percentile(&sorted, 50)),
+            secs(Self::percentile(&sorted, 95)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.to_string(), "requests=0");
+        for ms in [300, 100, 200, 400, 1000] {
+            stats.record(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            stats.to_string(),
+            "requests=5 min=0.10s max=1.00s mean=0.40s p50=0.30s p95=1.00s"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Terminal colors. They are only used when the stream is a terminal and
+//! `NO_COLOR` isn't set, unless disabled altogether with `--no-color`.
+
+use owo_colors::{OwoColorize, Stream};
+use std::fmt::Display;
+
+pub fn disable() {
+    owo_colors::set_override(false);
+}
+
+/// The REPL prompt marking the user's input.
+pub fn prompt() -> String {
+    "❯ "
+        .if_supports_color(Stream::Stdout, |t| t.cyan())
+        .to_string()
+}
+
+/// `[role]: ` prefixing an answer.
+pub fn role(role: &str) -> String {
+    let role = format!("[{}]:", role);
+    format!("{} ", role.if_supports_color(Stream::Stdout, |t| t.green()))
+}
+
+pub fn error(text: impl Display) -> String {
+    text.if_supports_color(Stream::Stderr, |t| t.red())
+        .to_string()
+}
+
+pub fn warning(text: impl Display) -> String {
+    text.if_supports_color(Stream::Stderr, |t| t.yellow())
+        .to_string()
+}
+
+/// Formats markdown for the terminal: headings, emphasis, lists, tables and
+/// code blocks, wrapped to `width` if given.
+pub fn markdown(text: &str, width: Option<usize>) -> String {
+    termimad::MadSkin::default().text(text, width).to_string()
+}
+
+/// Secondary information such as token usage.
+pub fn dim(text: impl Display) -> String {
+    text.if_supports_color(Stream::Stderr, |t| t.dimmed())
+        .to_string()
+}
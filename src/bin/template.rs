@@ -0,0 +1,76 @@
+//! `--template`: prompts with `{{name}}` placeholders filled in from `--var`.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Substitutes every `{{name}}` in `template`, allowing spaces around the
+/// name. Placeholders without a value are an error naming all of them,
+/// rather than being sent as they are.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut undefined = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unclosed {{ in the template".to_string())?;
+        let name = after[..end].trim();
+        match vars.get(name) {
+            Some(value) => rendered.push_str(value),
+            None => {
+                undefined.insert(name);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    if !undefined.is_empty() {
+        let names: Vec<&str> = undefined.into_iter().collect();
+        return Err(format!(
+            "no --var for the template variables {}",
+            names.join(", ")
+        ));
+    }
+    Ok(rendered)
+}
+
+/// Parses a `--var` as `NAME=VALUE`.
+pub fn parse_var(var: &str) -> Result<(String, String), String> {
+    let (name, value) = var
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", var))?;
+    Ok((name.trim().to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let template = "把{{ text }}翻译成{{lang}}，{{lang}}要地道。";
+        assert_eq!(
+            render(template, &vars(&[("text", "你好"), ("lang", "英文")])).unwrap(),
+            "把你好翻译成英文，英文要地道。"
+        );
+        assert_eq!(
+            render("no placeholders", &vars(&[])).unwrap(),
+            "no placeholders"
+        );
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        let err = render("{{b}} {{a}} {{b}}", &vars(&[])).unwrap_err();
+        assert_eq!(err, "no --var for the template variables a, b");
+        assert!(render("{{a", &vars(&[("a", "1")])).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! `--transcript`: a log of the conversation written as it happens.
+
+use baichuan_cli::ChatMessage;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    role: &'a str,
+    content: &'a str,
+}
+
+/// Appends every message to a file, flushed as soon as it is recorded so
+/// that the transcript survives a crash.
+pub struct Transcript {
+    file: LineWriter<File>,
+    json: bool,
+}
+
+impl Transcript {
+    /// Opens `path` for appending, writing one JSON object per message if
+    /// `json` and plain text otherwise.
+    pub fn open(path: &Path, json: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Transcript {
+            file: LineWriter::new(file),
+            json,
+        })
+    }
+
+    pub fn record(&mut self, message: &ChatMessage) -> io::Result<()> {
+        let line = format_entry(message, Local::now(), self.json);
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()
+    }
+}
+
+fn format_entry(message: &ChatMessage, time: DateTime<Local>, json: bool) -> String {
+    let timestamp = time.to_rfc3339_opts(SecondsFormat::Secs, false);
+    if json {
+        let entry = Entry {
+            timestamp,
+            role: &message.role,
+            content: &message.content,
+        };
+        serde_json::to_string(&entry).unwrap_or_default()
+    } else {
+        format!("{} [{}]: {}", timestamp, message.role, message.content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_entry() {
+        let time = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let timestamp = time.to_rfc3339_opts(SecondsFormat::Secs, false);
+        let message = ChatMessage::user("你好\n世界");
+        assert_eq!(
+            format_entry(&message, time, false),
+            format!("{} [user]: 你好\n世界", timestamp)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&format_entry(&message, time, true)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"timestamp": timestamp, "role": "user", "content": "你好\n世界"})
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! `--typewriter`: printing streamed answers a character at a time.
+
+use crate::out;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Pause after each character.
+const CHAR_DELAY: Duration = Duration::from_millis(12);
+/// Longest a chunk is spread over, so that printing keeps up with the
+/// stream even when it sends large chunks.
+const MAX_CHUNK_DELAY: Duration = Duration::from_millis(200);
+
+/// Enables the effect if asked to and stdout is a terminal. Piped output
+/// gets the chunks as they arrive.
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled && io::stdout().is_terminal(), Ordering::Relaxed);
+}
+
+/// Prints `text` and flushes stdout, a character at a time if enabled.
+pub async fn print(text: &str) {
+    let chars = text.chars().count() as u32;
+    if !ENABLED.load(Ordering::Relaxed) || chars < 2 {
+        out::print(text);
+        return;
+    }
+    let delay = CHAR_DELAY.min(MAX_CHUNK_DELAY / chars);
+    for c in text.chars() {
+        out::print(c);
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,216 @@
+//! `--wrap`: breaking plain answers into lines that fit the terminal.
+//!
+//! Lines are broken between words, or between any two wide characters such
+//! as Chinese, which take two columns each. Code blocks are left alone.
+
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use unicode_width::UnicodeWidthChar;
+
+/// Columns answers are wrapped to, 0 if they aren't.
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Wraps to `columns`, or with `None` to the width of the terminal if
+/// stdout is one. 0 disables wrapping.
+pub fn init(columns: Option<usize>) {
+    let columns = columns.unwrap_or_else(|| {
+        if io::stdout().is_terminal() {
+            terminal_size::terminal_size().map_or(0, |(width, _)| width.0 as usize)
+        } else {
+            0
+        }
+    });
+    WIDTH.store(columns, Ordering::Relaxed);
+}
+
+/// The width set by [`init`], if answers are wrapped.
+pub fn width() -> Option<usize> {
+    Some(WIDTH.load(Ordering::Relaxed)).filter(|&w| w > 0)
+}
+
+/// Wraps a complete text.
+pub fn text(text: &str) -> String {
+    let mut wrapper = Wrapper::new(width());
+    let mut wrapped = wrapper.push(text);
+    wrapped.push_str(&wrapper.finish());
+    wrapped
+}
+
+/// Wraps text arriving in pieces. A word is held back until it is known
+/// whether it fits on the current line, as is the start of a line until it
+/// is known whether it opens or closes a code block.
+pub struct Wrapper {
+    width: Option<usize>,
+    column: usize,
+    word: String,
+    word_width: usize,
+    /// Whitespace before `word`, dropped if the line is broken there
+    space: String,
+    space_width: usize,
+    /// The start of the current line, while it may still be a code fence
+    head: Option<String>,
+    in_code: bool,
+    /// The rest of the current line is printed as is
+    raw: bool,
+}
+
+impl Wrapper {
+    pub fn new(width: Option<usize>) -> Self {
+        Wrapper {
+            width,
+            column: 0,
+            word: String::new(),
+            word_width: 0,
+            space: String::new(),
+            space_width: 0,
+            head: Some(String::new()),
+            in_code: false,
+            raw: false,
+        }
+    }
+
+    /// The part of `text`, and of what was held back before it, that can be
+    /// printed now.
+    pub fn push(&mut self, text: &str) -> String {
+        let Some(width) = self.width else {
+            return text.to_string();
+        };
+        let mut out = String::new();
+        for c in text.chars() {
+            let Some(head) = &mut self.head else {
+                self.put(c, width, &mut out);
+                continue;
+            };
+            head.push(c);
+            let start = head.trim_start();
+            if c != '\n' && start.chars().count() < 3 {
+                continue;
+            }
+            if start.starts_with("```") {
+                self.in_code = !self.in_code;
+                self.raw = true;
+            } else {
+                self.raw = self.in_code;
+            }
+            let head = self.head.take().unwrap_or_default();
+            for c in head.chars() {
+                self.put(c, width, &mut out);
+            }
+        }
+        out
+    }
+
+    /// Whatever was still held back.
+    pub fn finish(&mut self) -> String {
+        let Some(width) = self.width else {
+            return String::new();
+        };
+        let mut out = String::new();
+        if let Some(head) = self.head.take() {
+            self.raw = self.in_code;
+            for c in head.chars() {
+                self.put(c, width, &mut out);
+            }
+        }
+        self.flush_word(width, &mut out);
+        out
+    }
+
+    fn put(&mut self, c: char, width: usize, out: &mut String) {
+        if c == '\n' {
+            self.flush_word(width, out);
+            self.space.clear();
+            self.space_width = 0;
+            out.push('\n');
+            self.column = 0;
+            self.head = Some(String::new());
+            self.raw = false;
+            return;
+        }
+        if self.raw {
+            out.push(c);
+            return;
+        }
+        let w = c.width().unwrap_or(0);
+        if c.is_whitespace() {
+            self.flush_word(width, out);
+            self.space.push(c);
+            self.space_width += w;
+        } else if w > 1 {
+            self.flush_word(width, out);
+            self.word.push(c);
+            self.word_width = w;
+            self.flush_word(width, out);
+        } else {
+            self.word.push(c);
+            self.word_width += w;
+            if self.word_width >= width {
+                // Longer than a line, so it is broken wherever it has to be.
+                self.flush_word(width, out);
+            }
+        }
+    }
+
+    fn flush_word(&mut self, width: usize, out: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        if self.column > 0 && self.column + self.space_width + self.word_width > width {
+            out.push('\n');
+            self.column = 0;
+        } else {
+            out.push_str(&self.space);
+            self.column += self.space_width;
+        }
+        self.space.clear();
+        self.space_width = 0;
+        out.push_str(&self.word);
+        self.column += self.word_width;
+        self.word.clear();
+        self.word_width = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wrap(text: &str, width: usize) -> String {
+        let mut wrapper = Wrapper::new(Some(width));
+        let mut wrapped = String::new();
+        // One character at a time, as the smallest pieces a stream can send.
+        for c in text.chars() {
+            wrapped.push_str(&wrapper.push(&c.to_string()));
+        }
+        wrapped.push_str(&wrapper.finish());
+        wrapped
+    }
+
+    #[test]
+    fn test_wrap_words() {
+        assert_eq!(wrap("hello big world", 10), "hello big\nworld");
+        assert_eq!(wrap("a\nbb cc", 5), "a\nbb cc");
+        assert_eq!(wrap("abcdefghij", 4), "abcd\nefgh\nij");
+        assert_eq!(wrap("hello world", 100), "hello world");
+    }
+
+    #[test]
+    fn test_wrap_wide_characters() {
+        // Each character takes two columns.
+        assert_eq!(wrap("你好世界", 5), "你好\n世界");
+        assert_eq!(wrap("ok 你好", 5), "ok 你\n好");
+    }
+
+    #[test]
+    fn test_code_blocks_are_not_wrapped() {
+        let text = "a b c\n```\nlet x = 1;\n```\nd e f";
+        assert_eq!(wrap(text, 3), "a b\nc\n```\nlet x = 1;\n```\nd e\nf");
+    }
+
+    #[test]
+    fn test_no_width() {
+        let mut wrapper = Wrapper::new(None);
+        assert_eq!(wrapper.push("hello world"), "hello world");
+        assert_eq!(wrapper.finish(), "");
+    }
+}
@@ -0,0 +1,80 @@
+//! Synchronous counterparts of the request functions, for callers that don't
+//! run a Tokio runtime. They must not be called from within an async context.
+
+use crate::api::{
+    build_request, endpoint, parse_resp, response_request_id, sign_request, user_messages,
+    warn_on_clock_skew, BaichuanResp, ChatMessage, HttpOptions, Model, Parameters, SignAlgo,
+    CHAT_PATH, DEFAULT_BASE_URL,
+};
+use crate::error::BaichuanError;
+use crate::wire;
+use reqwest::StatusCode;
+use tracing::{debug, info_span};
+
+/// Blocking version of [`crate::http_client`].
+pub fn http_client(options: &HttpOptions) -> Result<reqwest::blocking::Client, BaichuanError> {
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(options.timeout)
+        .connect_timeout(options.connect_timeout)
+        .user_agent(&options.user_agent)
+        .danger_accept_invalid_certs(options.danger_accept_invalid_certs);
+    if let Some(interval) = options.keepalive {
+        builder = builder.tcp_keepalive(interval).pool_idle_timeout(None);
+    }
+    if let Some(proxy) = options.proxy()? {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+/// Blocking version of [`crate::make_baichuan_request`].
+pub fn make_baichuan_request_blocking(
+    client: &reqwest::blocking::Client,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<String>,
+    parameters: &Parameters,
+) -> Result<BaichuanResp, BaichuanError> {
+    make_baichuan_request_with_history_blocking(
+        client,
+        api_key,
+        secret_key,
+        model,
+        user_messages(messages),
+        parameters,
+    )
+}
+
+/// Blocking version of [`crate::make_baichuan_request_with_history`].
+pub fn make_baichuan_request_with_history_blocking(
+    client: &reqwest::blocking::Client,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<ChatMessage>,
+    parameters: &Parameters,
+) -> Result<BaichuanResp, BaichuanError> {
+    let request = build_request(model, messages, parameters)?;
+    let url = endpoint(DEFAULT_BASE_URL, CHAT_PATH);
+    let signed = sign_request(url, api_key, secret_key, SignAlgo::Md5, &request, None)?;
+    let _span = info_span!("request", id = %signed.request_id, model = %model).entered();
+    debug!("starting request");
+    wire::log_request(&signed.url, &signed.headers, signed.body.as_bytes());
+    let response = client
+        .post(signed.url)
+        .headers(signed.headers)
+        .body(signed.body)
+        .send()?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    wire::log_response(status, &headers);
+    let body = response.bytes()?;
+    wire::log_body(&body);
+    if status != StatusCode::OK {
+        return Err(BaichuanError::status(status, &headers, &body));
+    }
+    let req_id = response_request_id(&headers, signed.request_id);
+    debug!(server_id = %req_id, "request was successful");
+    parse_resp(&body, Some(&req_id)).inspect_err(|e| warn_on_clock_skew(e, &headers))
+}
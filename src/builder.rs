@@ -0,0 +1,263 @@
+use crate::api::{
+    build_request, endpoint, send_request, sign_request, with_system_prompt, BaichuanResp,
+    ChatMessage, Model, Parameters, SignAlgo, CHAT_PATH, DEFAULT_BASE_URL,
+};
+use crate::error::BaichuanError;
+use crate::metrics::{RequestMetrics, DEFAULT_SLOW_REQUEST_THRESHOLD};
+use crate::transport::Transport;
+use std::time::Duration;
+
+/// A single chat request, configured through chainable setters and sent with
+/// [`RequestBuilder::send`]. Options that aren't set keep the API defaults.
+pub struct RequestBuilder<'a, T: Transport> {
+    transport: &'a T,
+    api_key: String,
+    secret_key: String,
+    model: Model,
+    messages: Vec<ChatMessage>,
+    parameters: Parameters,
+    system_prompt: Option<String>,
+    timeout: Option<Duration>,
+    sign_algo: SignAlgo,
+    base_url: String,
+    slow_request_threshold: Option<Duration>,
+    request_id: Option<String>,
+}
+
+impl<'a, T: Transport> RequestBuilder<'a, T> {
+    pub fn new(
+        transport: &'a T,
+        api_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        RequestBuilder {
+            transport,
+            api_key: api_key.into(),
+            secret_key: secret_key.into(),
+            model: Model::default(),
+            messages: vec![],
+            parameters: Parameters::default(),
+            system_prompt: None,
+            timeout: None,
+            sign_algo: SignAlgo::default(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            slow_request_threshold: Some(DEFAULT_SLOW_REQUEST_THRESHOLD),
+            request_id: None,
+        }
+    }
+
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// The conversation to answer, ending with the new prompt.
+    pub fn messages(mut self, messages: Vec<ChatMessage>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Replaces all sampling parameters set so far.
+    pub fn parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.parameters.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.parameters.top_p = Some(top_p);
+        self
+    }
+
+    pub fn top_k(mut self, top_k: i32) -> Self {
+        self.parameters.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_search_enhance(mut self, with_search_enhance: bool) -> Self {
+        self.parameters.with_search_enhance = Some(with_search_enhance);
+        self
+    }
+
+    /// See [`Parameters::seed`].
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.parameters.seed = Some(seed);
+        self
+    }
+
+    /// See [`Parameters::n`].
+    pub fn n(mut self, n: u32) -> Self {
+        self.parameters.n = Some(n);
+        self
+    }
+
+    /// Answers from the documents of this knowledge base.
+    pub fn knowledge_base_id(mut self, knowledge_base_id: impl Into<String>) -> Self {
+        self.parameters.knowledge_base_id = Some(knowledge_base_id.into());
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.parameters.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Adds a text the answer stops in front of.
+    pub fn stop(mut self, stop: impl Into<String>) -> Self {
+        self.parameters
+            .stop
+            .get_or_insert_with(Vec::new)
+            .push(stop.into());
+        self
+    }
+
+    /// Sent as a `system` message ahead of the conversation.
+    pub fn system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    /// Gives up on this request after `timeout`, overriding the timeout of
+    /// the transport.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn sign_algo(mut self, sign_algo: SignAlgo) -> Self {
+        self.sign_algo = sign_algo;
+        self
+    }
+
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Warns about the request if it takes longer than `threshold`, `None`
+    /// to never warn. Defaults to [`DEFAULT_SLOW_REQUEST_THRESHOLD`].
+    pub fn slow_request_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_request_threshold = threshold;
+        self
+    }
+
+    /// Sends the request with this `X-BC-Request-Id` rather than a fresh
+    /// one, e.g. to retry it under the id of the attempt it repeats.
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub async fn send(self) -> Result<BaichuanResp, BaichuanError> {
+        Ok(self.send_with_metrics().await?.0)
+    }
+
+    /// Like [`send`](Self::send), also returning the sizes and timing of the
+    /// request.
+    pub async fn send_with_metrics(self) -> Result<(BaichuanResp, RequestMetrics), BaichuanError> {
+        let messages = with_system_prompt(self.system_prompt.as_deref(), self.messages);
+        let request = build_request(self.model, messages, &self.parameters)?;
+        let signed = sign_request(
+            endpoint(&self.base_url, CHAT_PATH),
+            &self.api_key,
+            &self.secret_key,
+            self.sign_algo,
+            &request,
+            self.request_id.as_deref(),
+        )?;
+        send_request(self.transport, signed, self.model, self.timeout)
+            .await
+            .inspect(|(_, metrics)| metrics.warn_if_slow(self.slow_request_threshold))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::TransportResponse;
+    use reqwest::header::HeaderMap;
+    use std::sync::Mutex;
+
+    /// The url, body and timeout of a request.
+    type Sent = (String, Vec<u8>, Option<Duration>);
+
+    /// Remembers what was sent and answers with an empty success response.
+    #[derive(Default)]
+    struct RecordingTransport {
+        sent: Mutex<Option<Sent>>,
+    }
+
+    impl Transport for RecordingTransport {
+        async fn post_json(
+            &self,
+            url: &str,
+            _headers: HeaderMap,
+            body: Vec<u8>,
+            timeout: Option<Duration>,
+        ) -> Result<TransportResponse, BaichuanError> {
+            *self.sent.lock().unwrap() = Some((url.to_string(), body, timeout));
+            Ok(TransportResponse {
+                headers: HeaderMap::new(),
+                body: br#"{"code":0,"msg":"success"}"#.to_vec(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_send() -> Result<(), BaichuanError> {
+        let transport = RecordingTransport::default();
+        RequestBuilder::new(&transport, "api_key", "secret_key")
+            .model(Model::Baichuan2Turbo)
+            .messages(vec![ChatMessage::user("你好")])
+            .system_prompt("Be brief.")
+            .temperature(0.5)
+            .stop("\n\n")
+            .timeout(Duration::from_secs(5))
+            .base_url("http://localhost:8080/")
+            .send()
+            .await?;
+        let (url, body, timeout) = transport.sent.lock().unwrap().take().expect("nothing sent");
+        assert_eq!(url, "http://localhost:8080/v1/chat");
+        assert_eq!(timeout, Some(Duration::from_secs(5)));
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("cannot parse");
+        assert_eq!(body["model"], "Baichuan2-Turbo");
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "你好");
+        assert_eq!(body["parameters"]["temperature"], 0.5);
+        assert_eq!(body["parameters"]["stop"], serde_json::json!(["\n\n"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_builder_metrics() -> Result<(), BaichuanError> {
+        let transport = RecordingTransport::default();
+        let (_, metrics) = RequestBuilder::new(&transport, "api_key", "secret_key")
+            .messages(vec![ChatMessage::user("你好")])
+            .send_with_metrics()
+            .await?;
+        let (_, body, _) = transport.sent.lock().unwrap().take().expect("nothing sent");
+        assert_eq!(metrics.request_bytes, body.len());
+        assert_eq!(
+            metrics.response_bytes,
+            r#"{"code":0,"msg":"success"}"#.len()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_builder_rejects_invalid_parameters() {
+        let transport = RecordingTransport::default();
+        let err = RequestBuilder::new(&transport, "api_key", "secret_key")
+            .messages(vec![ChatMessage::user("你好")])
+            .top_p(1.5)
+            .send()
+            .await
+            .expect_err("top_p is out of range");
+        assert!(matches!(err, BaichuanError::InvalidParameter(_)));
+        assert!(transport.sent.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,451 @@
+use crate::api::{
+    build_request, endpoint, http_client, new_request_id, send_request, server_clock_skew,
+    sign_request, warn_if_skewed, with_system_prompt, BaichuanReq, BaichuanResp, ChatMessage,
+    HttpOptions, Model, Parameters, RespCode, SignAlgo, SignedRequest, CHAT_PATH, DEFAULT_BASE_URL,
+};
+use crate::embedding::send_embedding_request;
+use crate::error::BaichuanError;
+use crate::hook::{run_hooks, ResponseHook};
+use crate::keys::{KeyPair, KeyRing};
+use crate::metrics::{RequestMetrics, DEFAULT_SLOW_REQUEST_THRESHOLD};
+use crate::mock::MockTransport;
+use crate::retry::{retry_with_backoff, RetryPolicy};
+use crate::stream::{send_buffered_stream_request, send_stream_request, StreamEvent, STREAM_PATH};
+use futures::future::Either;
+use futures::Stream;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Credentials and settings shared by every request of a session, so the
+/// underlying connection pool is reused between calls.
+pub struct BaichuanClient {
+    http: reqwest::Client,
+    keys: KeyRing,
+    model: Model,
+    parameters: Parameters,
+    retry_policy: RetryPolicy,
+    system_prompt: Option<String>,
+    sign_algo: SignAlgo,
+    base_url: String,
+    slow_request_threshold: Option<Duration>,
+    hooks: Vec<Box<dyn ResponseHook>>,
+    fallback_models: Vec<Model>,
+    /// Answer with [`MockTransport`] instead of sending requests
+    mock: bool,
+}
+
+impl BaichuanClient {
+    /// A client for the default model, with an HTTP client built from the
+    /// default [`HttpOptions`].
+    ///
+    /// # Panics
+    ///
+    /// Like [`reqwest::Client::new`], if the TLS backend can't be
+    /// initialized. Use [`from_http`](Self::from_http) to handle that.
+    pub fn new(api_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+        let http = http_client(&HttpOptions::default()).expect("cannot build the HTTP client");
+        Self::from_http(http, api_key, secret_key)
+    }
+
+    /// A client sending its requests with `http`, e.g. one built by
+    /// [`crate::http_client`] with a proxy.
+    pub fn from_http(
+        http: reqwest::Client,
+        api_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        BaichuanClient {
+            http,
+            keys: KeyRing::new(api_key.into(), secret_key.into()),
+            model: Model::default(),
+            parameters: Parameters::default(),
+            retry_policy: RetryPolicy::default(),
+            system_prompt: None,
+            sign_algo: SignAlgo::default(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            slow_request_threshold: Some(DEFAULT_SLOW_REQUEST_THRESHOLD),
+            hooks: Vec::new(),
+            fallback_models: Vec::new(),
+            mock: false,
+        }
+    }
+
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Models to send a request with in turn when the server fails to
+    /// answer it with the client's own, i.e. with
+    /// [`RespCode::InternalError`] or [`RespCode::SystemError`] once the
+    /// retries are used up. Errors caused by the request or the account,
+    /// such as a bad key, are returned without trying them.
+    pub fn with_fallback_models(mut self, models: Vec<Model>) -> Self {
+        self.fallback_models = models;
+        self
+    }
+
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sends `system_prompt` as a `system` message ahead of every
+    /// conversation, without it having to be part of the history.
+    pub fn with_system_prompt(mut self, system_prompt: Option<String>) -> Self {
+        self.system_prompt = system_prompt;
+        self
+    }
+
+    pub fn with_sign_algo(mut self, sign_algo: SignAlgo) -> Self {
+        self.sign_algo = sign_algo;
+        self
+    }
+
+    /// Sends requests to `base_url` instead of [`DEFAULT_BASE_URL`], e.g. a
+    /// gateway or a mock server. See [`crate::validate_base_url`].
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Uses another api key in turn with the ones added before, e.g. to get
+    /// past the rate limit of a single key. A key the API rejects, say
+    /// because it expired, is dropped from the rotation unless it is the
+    /// last one left.
+    pub fn with_additional_key(
+        mut self,
+        api_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        self.keys.add(api_key.into(), secret_key.into());
+        self
+    }
+
+    /// Sends at most `rpm` requests per minute with each api key, waiting
+    /// before a request that would exceed it. Unlimited by default; the API
+    /// allows 10 per api key.
+    pub fn with_rate_limit(mut self, rpm: u32) -> Self {
+        self.keys.set_rate_limit(rpm);
+        self
+    }
+
+    /// Warns about requests taking longer than `threshold`, `None` to never
+    /// warn. Defaults to [`DEFAULT_SLOW_REQUEST_THRESHOLD`]. Streams are
+    /// exempt, as they take as long as the answer is.
+    pub fn with_slow_request_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_request_threshold = threshold;
+        self
+    }
+
+    /// Runs `hook` on every response returned by [`send`](Self::send), after
+    /// the hooks added before it.
+    pub fn with_response_hook(mut self, hook: impl ResponseHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Answers every request locally with [`MockTransport`] instead of
+    /// sending it, so the credentials needn't be valid. Embeddings fail.
+    pub fn with_mock_transport(mut self) -> Self {
+        self.mock = true;
+        self
+    }
+
+    /// How long the next request will wait for the rate limit.
+    pub fn throttle_time(&self) -> Duration {
+        self.keys.wait_time()
+    }
+
+    /// Runs `send` with the next key once its rate limit allows, and again
+    /// with another key if the API rejects that one.
+    async fn with_key<'s, T, F, Fut>(&'s self, send: F) -> Result<T, BaichuanError>
+    where
+        F: Fn(&'s KeyPair) -> Fut,
+        Fut: Future<Output = Result<T, BaichuanError>>,
+    {
+        loop {
+            let key = self.keys.pick();
+            key.acquire().await;
+            match send(key).await {
+                Err(e) if self.keys.drop_if_rejected(key, &e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Switches the model used by subsequent requests.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// Replaces the parameters used by subsequent requests.
+    pub fn set_parameters(&mut self, parameters: Parameters) {
+        self.parameters = parameters;
+    }
+
+    fn build(
+        &self,
+        model: Model,
+        messages: Vec<ChatMessage>,
+    ) -> Result<BaichuanReq, BaichuanError> {
+        let messages = with_system_prompt(self.system_prompt.as_deref(), messages);
+        build_request(model, messages, &self.parameters)
+    }
+
+    /// Whether to send the request again with `next` after it failed with
+    /// `model`, warning about the switch if so. Only the server failing
+    /// might be helped by another model.
+    fn fall_back(err: &BaichuanError, model: Model, next: Option<Model>) -> Option<Model> {
+        let failed = matches!(
+            err.resp_code(),
+            Some(RespCode::InternalError | RespCode::SystemError)
+        );
+        let next = next.filter(|_| failed)?;
+        warn!("{} failed, falling back to {}: {}", model, next, err);
+        Some(next)
+    }
+
+    /// The signed request [`send`](Self::send) would post, without sending it.
+    pub fn prepare(&self, messages: Vec<ChatMessage>) -> Result<SignedRequest, BaichuanError> {
+        self.sign(CHAT_PATH, messages)
+    }
+
+    /// The JSON body [`send`](Self::send) would post, e.g. to tell identical
+    /// requests apart. Unlike [`prepare`](Self::prepare), it takes no key,
+    /// so the rotation of the keys is left as it is.
+    pub fn request_body(&self, messages: Vec<ChatMessage>) -> Result<String, BaichuanError> {
+        serde_json::to_string(&self.build(self.model, messages)?)
+            .map_err(BaichuanError::Serialization)
+    }
+
+    /// The signed request [`stream`](Self::stream) would post, without sending it.
+    pub fn prepare_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Result<SignedRequest, BaichuanError> {
+        self.sign(STREAM_PATH, messages)
+    }
+
+    fn sign(&self, path: &str, messages: Vec<ChatMessage>) -> Result<SignedRequest, BaichuanError> {
+        let key = self.keys.pick();
+        sign_request(
+            endpoint(&self.base_url, path),
+            &key.api_key,
+            &key.secret_key,
+            self.sign_algo,
+            &self.build(self.model, messages)?,
+            None,
+        )
+    }
+
+    /// How far the local clock is ahead of the server's, warning if it is
+    /// far enough off for requests to be rejected as expired. `None` if the
+    /// server didn't say what time it is. Worth checking once up front, as
+    /// a wrong clock breaks every request.
+    pub async fn check_clock(&self) -> Result<Option<chrono::Duration>, BaichuanError> {
+        if self.mock {
+            return Ok(None);
+        }
+        let skew = server_clock_skew(&self.http, &self.base_url).await?;
+        if let Some(skew) = skew {
+            warn_if_skewed(skew);
+        }
+        Ok(skew)
+    }
+
+    /// Sends the conversation and waits for the complete answer, retrying
+    /// according to the client's [`RetryPolicy`].
+    pub async fn send(&self, messages: Vec<ChatMessage>) -> Result<BaichuanResp, BaichuanError> {
+        Ok(self.send_with_metrics(messages).await?.0)
+    }
+
+    /// Like [`send`](Self::send), also returning the sizes and timing of the
+    /// attempt that succeeded.
+    pub async fn send_with_metrics(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Result<(BaichuanResp, RequestMetrics), BaichuanError> {
+        let messages = with_system_prompt(self.system_prompt.as_deref(), messages);
+        self.send_as(messages, &self.parameters, &self.fallback_models)
+            .await
+    }
+
+    /// Like [`send`](Self::send), with the default [`Parameters`], without
+    /// the system prompt and without falling back to other models: for
+    /// requests of the application's own, such as summarizing the
+    /// conversation, which the settings for the answers shouldn't shape.
+    pub async fn send_plain(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Result<BaichuanResp, BaichuanError> {
+        let (resp, _) = self.send_as(messages, &Parameters::default(), &[]).await?;
+        Ok(resp)
+    }
+
+    async fn send_as(
+        &self,
+        messages: Vec<ChatMessage>,
+        parameters: &Parameters,
+        fallback_models: &[Model],
+    ) -> Result<(BaichuanResp, RequestMetrics), BaichuanError> {
+        let mut fallbacks = fallback_models.iter().copied();
+        let mut model = self.model;
+        let (mut resp, metrics) = loop {
+            let request = build_request(model, messages.clone(), parameters)?;
+            let request_id = new_request_id();
+            let result = retry_with_backoff(&self.retry_policy, || {
+                self.with_key(|key| async {
+                    let signed = sign_request(
+                        endpoint(&self.base_url, CHAT_PATH),
+                        &key.api_key,
+                        &key.secret_key,
+                        self.sign_algo,
+                        &request,
+                        Some(&request_id),
+                    )?;
+                    let result = if self.mock {
+                        send_request(&MockTransport, signed, model, None).await
+                    } else {
+                        send_request(&self.http, signed, model, None).await
+                    };
+                    result.inspect(|(_, metrics)| metrics.warn_if_slow(self.slow_request_threshold))
+                })
+            })
+            .await;
+            match result {
+                Err(e) => match Self::fall_back(&e, model, fallbacks.next()) {
+                    Some(next) => model = next,
+                    None => return Err(e),
+                },
+                Ok(result) => break result,
+            }
+        };
+        run_hooks(&self.hooks, &mut resp);
+        Ok((resp, metrics))
+    }
+
+    /// Embeds each of the `input` texts, retrying like [`send`](Self::send).
+    pub async fn embed(&self, input: Vec<String>) -> Result<Vec<Vec<f32>>, BaichuanError> {
+        let request_id = new_request_id();
+        retry_with_backoff(&self.retry_policy, || {
+            self.with_key(|key| async {
+                if self.mock {
+                    return Err(BaichuanError::Config(
+                        "embeddings can't be mocked".to_string(),
+                    ));
+                }
+                send_embedding_request(
+                    &self.http,
+                    &self.base_url,
+                    &key.api_key,
+                    &key.secret_key,
+                    self.sign_algo,
+                    &input,
+                    Some(&request_id),
+                )
+                .await
+            })
+        })
+        .await
+    }
+
+    /// Sends the conversation and streams the answer as it is generated.
+    /// Only establishing the stream is retried, or falls back to another
+    /// model.
+    pub async fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, BaichuanError>> + '_, BaichuanError> {
+        let mut fallbacks = self.fallback_models.iter().copied();
+        let mut model = self.model;
+        loop {
+            let request = self.build(model, messages.clone())?;
+            let request_id = new_request_id();
+            let result = retry_with_backoff(&self.retry_policy, || {
+                self.with_key(|key| async {
+                    if self.mock {
+                        let signed = sign_request(
+                            endpoint(&self.base_url, STREAM_PATH),
+                            &key.api_key,
+                            &key.secret_key,
+                            self.sign_algo,
+                            &request,
+                            Some(&request_id),
+                        )?;
+                        let stream = send_buffered_stream_request(&MockTransport, signed).await?;
+                        return Ok(Either::Left(stream));
+                    }
+                    send_stream_request(
+                        &self.http,
+                        &self.base_url,
+                        &key.api_key,
+                        &key.secret_key,
+                        self.sign_algo,
+                        &request,
+                        Some(&request_id),
+                    )
+                    .await
+                    .map(Either::Right)
+                })
+            })
+            .await;
+            match result {
+                Err(e) => match Self::fall_back(&e, model, fallbacks.next()) {
+                    Some(next) => model = next,
+                    None => return Err(e),
+                },
+                Ok(stream) => return Ok(stream),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new_client() -> Result<(), BaichuanError> {
+        let client = BaichuanClient::new("api key", "secret key")
+            .with_model(Model::Baichuan2Turbo)
+            .with_base_url("https://gateway.example.com");
+        let request = client.prepare(vec![ChatMessage::user("你好")])?;
+        assert_eq!(request.url, "https://gateway.example.com/v1/chat");
+        assert!(request.body.contains(r#""model":"Baichuan2-Turbo""#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_body_takes_no_key() -> Result<(), BaichuanError> {
+        let client =
+            BaichuanClient::new("key-a", "secret-a").with_additional_key("key-b", "secret-b");
+        let messages = vec![ChatMessage::user("你好")];
+        let body = client.request_body(messages.clone())?;
+        let request = client.prepare(messages)?;
+        assert_eq!(body, request.body);
+        assert_eq!(
+            request.headers[reqwest::header::AUTHORIZATION],
+            "Bearer key-a"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Text embeddings, e.g. for retrieval-augmented generation.
+
+use crate::api::{
+    check_resp, endpoint, response_request_id, sign_request, BaichuanResp, RespCode, SignAlgo,
+    DEFAULT_BASE_URL,
+};
+use crate::error::BaichuanError;
+use crate::transport::{Transport, TransportResponse};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info_span, Instrument};
+
+pub(crate) const EMBEDDINGS_PATH: &str = "/v1/embeddings";
+
+/// The only embedding model the API offers.
+pub const EMBEDDING_MODEL: &str = "Baichuan-Text-Embedding";
+
+/// How many texts a single request may embed.
+pub const MAX_EMBEDDING_INPUTS: usize = 16;
+
+#[derive(Serialize, Debug)]
+struct EmbeddingReq<'a> {
+    model: &'static str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize, Debug)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// The embeddings, or only a code and message if the request was rejected.
+#[derive(Deserialize, Debug)]
+struct EmbeddingResp {
+    #[serde(default)]
+    code: Option<RespCode>,
+    #[serde(default)]
+    msg: String,
+    #[serde(default)]
+    data: Vec<Embedding>,
+}
+
+/// Signs and sends one embeddings request, returning a vector per input in
+/// the order of `input`.
+pub(crate) async fn send_embedding_request(
+    transport: &impl Transport,
+    base_url: &str,
+    api_key: &str,
+    secret_key: &str,
+    sign_algo: SignAlgo,
+    input: &[String],
+    request_id: Option<&str>,
+) -> Result<Vec<Vec<f32>>, BaichuanError> {
+    if input.is_empty() || input.len() > MAX_EMBEDDING_INPUTS {
+        return Err(BaichuanError::InvalidParameter(format!(
+            "between 1 and {} texts can be embedded at once, got {}",
+            MAX_EMBEDDING_INPUTS,
+            input.len()
+        )));
+    }
+    let request = EmbeddingReq {
+        model: EMBEDDING_MODEL,
+        input,
+    };
+    let url = endpoint(base_url, EMBEDDINGS_PATH);
+    let signed = sign_request(url, api_key, secret_key, sign_algo, &request, request_id)?;
+    let span = info_span!("request", id = %signed.request_id, model = EMBEDDING_MODEL);
+    async move {
+        debug!(inputs = input.len(), "starting request");
+        let TransportResponse { headers, body } = transport
+            .post_json(&signed.url, signed.headers, signed.body.into_bytes(), None)
+            .await?;
+        let req_id = response_request_id(&headers, signed.request_id);
+        let resp: EmbeddingResp =
+            serde_json::from_slice(&body).map_err(|e| BaichuanError::parse(e, &body))?;
+        if let Some(code) = resp.code {
+            check_resp(
+                BaichuanResp {
+                    code,
+                    msg: resp.msg,
+                    data: None,
+                    usage: None,
+                },
+                Some(&req_id),
+            )?;
+        }
+        let mut data = resp.data;
+        data.sort_by_key(|embedding| embedding.index);
+        debug!(server_id = %req_id, "request was successful");
+        Ok(data.into_iter().map(|e| e.embedding).collect())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Embeds each of the `input` texts, at most [`MAX_EMBEDDING_INPUTS`] of them.
+pub async fn make_embedding_request(
+    client: &impl Transport,
+    api_key: &str,
+    secret_key: &str,
+    input: Vec<String>,
+) -> Result<Vec<Vec<f32>>, BaichuanError> {
+    send_embedding_request(
+        client,
+        DEFAULT_BASE_URL,
+        api_key,
+        secret_key,
+        SignAlgo::Md5,
+        &input,
+        None,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    struct CannedTransport(&'static str);
+
+    impl Transport for CannedTransport {
+        async fn post_json(
+            &self,
+            url: &str,
+            _headers: HeaderMap,
+            body: Vec<u8>,
+            _timeout: Option<Duration>,
+        ) -> Result<TransportResponse, BaichuanError> {
+            assert!(url.ends_with(EMBEDDINGS_PATH));
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["model"], EMBEDDING_MODEL);
+            Ok(TransportResponse {
+                headers: HeaderMap::new(),
+                body: self.0.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    async fn canned_embedding(
+        body: &'static str,
+        input: &[&str],
+    ) -> Result<Vec<Vec<f32>>, BaichuanError> {
+        make_embedding_request(
+            &CannedTransport(body),
+            "api_key",
+            "secret_key",
+            input.iter().map(|s| s.to_string()).collect(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_in_input_order() -> Result<(), BaichuanError> {
+        let body = r#"{"object":"list","model":"Baichuan-Text-Embedding","data":[
+            {"object":"embedding","index":1,"embedding":[0.5,0.25]},
+            {"object":"embedding","index":0,"embedding":[1.0,-1.0]}
+        ],"usage":{"prompt_tokens":4,"total_tokens":4}}"#;
+        let vectors = canned_embedding(body, &["你好", "世界"]).await?;
+        assert_eq!(vectors, vec![vec![1.0, -1.0], vec![0.5, 0.25]]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embedding_errors() {
+        let err = canned_embedding(r#"{"code":10101,"msg":"Invalid apikey"}"#, &["你好"])
+            .await
+            .expect_err("should be rejected");
+        assert_eq!(err.resp_code(), Some(RespCode::InvalidApikey));
+        let err = canned_embedding("{}", &[])
+            .await
+            .expect_err("should need input");
+        assert!(matches!(err, BaichuanError::InvalidParameter(_)));
+    }
+}
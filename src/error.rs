@@ -0,0 +1,290 @@
+use crate::api::RespCode;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::StatusCode;
+use std::fmt;
+use tracing::debug;
+
+#[derive(Debug)]
+pub enum BaichuanError {
+    /// The request could not be sent or the response body could not be read.
+    Request(reqwest::Error),
+    /// No complete response arrived within the configured timeout.
+    Timeout(reqwest::Error),
+    /// The request body could not be serialized.
+    Serialization(serde_json::Error),
+    /// The response body is not the JSON we expected. `body_snippet` is the
+    /// start of what was received instead, e.g. an HTML error page.
+    Parse {
+        source: serde_json::Error,
+        body_snippet: String,
+    },
+    /// The server answered with a non-200 HTTP status, e.g. 429 when rate
+    /// limiting or 500 when failing. `body` is the whole response body.
+    Http { status: StatusCode, body: String },
+    /// A proxy or load balancer in front of the API answered with a non-200
+    /// HTTP status and a page that isn't JSON, so the request most likely
+    /// never reached the API. `snippet` is the start of the page.
+    Gateway { status: StatusCode, snippet: String },
+    /// The server answered, but with a non-success [`RespCode`]. Quote the
+    /// request id when contacting Baichuan support.
+    Api {
+        code: RespCode,
+        msg: String,
+        request_id: Option<String>,
+    },
+    /// The signed headers could not be built.
+    Signature(String),
+    /// A request parameter is out of its documented range.
+    InvalidParameter(String),
+    /// Reading or writing a local file failed.
+    Io(std::io::Error),
+    /// The api key or secret key is unusable.
+    InvalidCredentials(String),
+    /// A local setting such as a URL can't be used.
+    Config(String),
+    /// The request succeeded, but the answer isn't the JSON that was asked
+    /// for. `answer` is the whole answer text.
+    AnswerNotJson {
+        source: serde_json::Error,
+        answer: String,
+    },
+}
+
+impl fmt::Display for BaichuanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaichuanError::Request(e) => write!(f, "{}", e),
+            BaichuanError::Timeout(e) => write!(f, "request timed out: {}", e),
+            BaichuanError::Serialization(e) => write!(f, "{}", e),
+            BaichuanError::Parse {
+                source,
+                body_snippet,
+            } => write!(
+                f,
+                "failed to parse json: {}, body: {:?}",
+                source, body_snippet
+            ),
+            BaichuanError::Http { status, body } => write!(f, "HTTP error {}: {:?}", status, body),
+            BaichuanError::Gateway { status, snippet } => {
+                write!(f, "gateway error {}: {:?}", status, snippet)
+            }
+            BaichuanError::Api { code, msg, .. } => {
+                let msg = msg.trim_end_matches('.');
+                write!(
+                    f,
+                    "request failed with {:?}: {}. {}",
+                    code,
+                    msg,
+                    code.user_hint()
+                )
+            }
+            BaichuanError::Signature(reason) => write!(f, "failed to sign request: {}", reason),
+            BaichuanError::InvalidParameter(reason) => write!(f, "invalid parameter: {}", reason),
+            BaichuanError::Io(e) => write!(f, "{}", e),
+            BaichuanError::InvalidCredentials(reason) => {
+                write!(f, "invalid credentials: {}", reason)
+            }
+            BaichuanError::Config(reason) => write!(f, "{}", reason),
+            BaichuanError::AnswerNotJson { source, .. } => {
+                write!(f, "the answer is not the expected JSON: {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BaichuanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BaichuanError::Request(e) | BaichuanError::Timeout(e) => Some(e),
+            BaichuanError::Serialization(e)
+            | BaichuanError::Parse { source: e, .. }
+            | BaichuanError::AnswerNotJson { source: e, .. } => Some(e),
+            BaichuanError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BaichuanError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            BaichuanError::Timeout(e)
+        } else {
+            BaichuanError::Request(e)
+        }
+    }
+}
+
+/// How much of an unparsable body is kept in [`BaichuanError::Parse`].
+const BODY_SNIPPET_CHARS: usize = 200;
+
+impl BaichuanError {
+    /// A [`BaichuanError::Parse`] for the unparsable `body`.
+    pub(crate) fn parse(source: serde_json::Error, body: &[u8]) -> Self {
+        BaichuanError::Parse {
+            source,
+            body_snippet: snippet(body),
+        }
+    }
+
+    /// The error for a response with a non-200 `status`: a
+    /// [`BaichuanError::Gateway`] unless the body is JSON, which comes from
+    /// the API itself.
+    pub(crate) fn status(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Self {
+        let is_json = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        if is_json || body.is_empty() {
+            return BaichuanError::Http {
+                status,
+                body: String::from_utf8_lossy(body).into_owned(),
+            };
+        }
+        debug!(body = %String::from_utf8_lossy(body), "gateway error page");
+        BaichuanError::Gateway {
+            status,
+            snippet: snippet(body),
+        }
+    }
+
+    /// The API response code, if the server rejected the request.
+    pub fn resp_code(&self) -> Option<RespCode> {
+        match self {
+            BaichuanError::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status the server answered with, if it wasn't `200 OK`.
+    pub fn http_status(&self) -> Option<StatusCode> {
+        match self {
+            BaichuanError::Http { status, .. } | BaichuanError::Gateway { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    /// The id of the rejected request, as echoed by the server or else as sent.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            BaichuanError::Api { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the request may succeed if sent again later: the API is rate
+    /// limiting us, the account is locked for the time being or the server
+    /// is temporarily unavailable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BaichuanError::Api { code, .. } => matches!(
+                code,
+                RespCode::AccountRequestTooFrequent | RespCode::AccountTempLocked
+            ),
+            BaichuanError::Http { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::SERVICE_UNAVAILABLE
+            }
+            BaichuanError::Gateway { status, .. } => {
+                *status == StatusCode::BAD_GATEWAY
+                    || *status == StatusCode::SERVICE_UNAVAILABLE
+                    || *status == StatusCode::GATEWAY_TIMEOUT
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The start of `body`, cut off after [`BODY_SNIPPET_CHARS`].
+fn snippet(body: &[u8]) -> String {
+    let body = String::from_utf8_lossy(body);
+    let mut snippet = truncate_str(&body, BODY_SNIPPET_CHARS).to_string();
+    if snippet.len() < body.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// The first `max_chars` characters of `s`. Unlike slicing at a byte index,
+/// this can't split a character such as a Chinese one, which takes several
+/// bytes.
+pub(crate) fn truncate_str(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_truncate_str() {
+        assert_eq!(truncate_str("你好世界", 2), "你好");
+        assert_eq!(truncate_str("a你b", 2), "a你");
+        assert_eq!(truncate_str("你好", 2), "你好");
+        assert_eq!(truncate_str("你好", 10), "你好");
+        assert_eq!(truncate_str("你好", 0), "");
+        // 4 bytes each, so a byte limit would fall inside one.
+        let body = "😀".repeat(BODY_SNIPPET_CHARS * 2);
+        assert_eq!(
+            snippet(body.as_bytes()).chars().count(),
+            BODY_SNIPPET_CHARS + 1
+        );
+    }
+
+    #[test]
+    fn test_html_error_page_is_a_gateway_error() {
+        let page = format!(
+            "<html>\r\n<head><title>502 Bad Gateway</title></head>\r\n<body>{}</body>\r\n</html>",
+            "<center><h1>502 Bad Gateway</h1></center>".repeat(10)
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/html".parse().unwrap());
+        let err = BaichuanError::status(StatusCode::BAD_GATEWAY, &headers, page.as_bytes());
+        let BaichuanError::Gateway { status, snippet } = &err else {
+            panic!("not a gateway error: {:?}", err);
+        };
+        assert_eq!(*status, StatusCode::BAD_GATEWAY);
+        assert!(snippet.starts_with("<html>\r\n<head><title>502 Bad Gateway"));
+        assert_eq!(snippet.chars().count(), BODY_SNIPPET_CHARS + 1);
+        assert!(err
+            .to_string()
+            .starts_with("gateway error 502 Bad Gateway: "));
+        assert!(err.is_retryable());
+
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        let body = br#"{"code":1,"msg":"bad request"}"#;
+        let err = BaichuanError::status(StatusCode::BAD_REQUEST, &headers, body);
+        assert!(matches!(err, BaichuanError::Http { .. }));
+    }
+
+    #[test]
+    fn test_http_status() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        let body = br#"{"error":"rate limited"}"#;
+        let err = BaichuanError::status(StatusCode::TOO_MANY_REQUESTS, &headers, body);
+        assert_eq!(err.http_status(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(
+            err.to_string(),
+            r#"HTTP error 429 Too Many Requests: "{\"error\":\"rate limited\"}""#
+        );
+        assert!(err.is_retryable());
+
+        let err = BaichuanError::status(StatusCode::INTERNAL_SERVER_ERROR, &headers, b"");
+        assert_eq!(err.http_status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(err.to_string().starts_with("HTTP error 500 "));
+        assert!(!err.is_retryable());
+
+        let err = BaichuanError::status(StatusCode::UNAUTHORIZED, &headers, b"{}");
+        let BaichuanError::Http { status, body } = &err else {
+            panic!("not an HTTP error: {:?}", err);
+        };
+        assert_eq!((*status, body.as_str()), (StatusCode::UNAUTHORIZED, "{}"));
+        assert_eq!(BaichuanError::Config("x".into()).http_status(), None);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::api::BaichuanResp;
+
+/// Runs on every complete response a [`BaichuanClient`](crate::BaichuanClient)
+/// returns, e.g. to collect usage or to strip markup from the answers.
+/// Streamed answers don't go through hooks.
+///
+/// Closures taking `&mut BaichuanResp` are hooks too.
+pub trait ResponseHook: Send + Sync {
+    /// Inspects `resp`, which may be modified before it is returned.
+    fn on_response(&self, resp: &mut BaichuanResp);
+}
+
+impl<F> ResponseHook for F
+where
+    F: Fn(&mut BaichuanResp) + Send + Sync,
+{
+    fn on_response(&self, resp: &mut BaichuanResp) {
+        self(resp)
+    }
+}
+
+/// Runs `hooks` on `resp` in the order they were added.
+pub(crate) fn run_hooks(hooks: &[Box<dyn ResponseHook>], resp: &mut BaichuanResp) {
+    for hook in hooks {
+        hook.on_response(resp);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{BaichuanData, ChatMessage, RespCode, UsageInfo};
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    struct CountTokens(Arc<AtomicI64>);
+
+    impl ResponseHook for CountTokens {
+        fn on_response(&self, resp: &mut BaichuanResp) {
+            if let Some(usage) = &resp.usage {
+                self.0.fetch_add(usage.total_tokens, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hooks_run_in_order() {
+        let mut resp = BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: vec![ChatMessage::assistant("<think>hmm</think>你好")],
+            }),
+            usage: Some(UsageInfo {
+                prompt_tokens: 3,
+                answer_tokens: 5,
+                total_tokens: 8,
+            }),
+        };
+        let tokens = Arc::new(AtomicI64::new(0));
+        let strip_thinking = |resp: &mut BaichuanResp| {
+            for message in resp.data.iter_mut().flat_map(|d| &mut d.messages) {
+                if let Some((_, answer)) = message.content.split_once("</think>") {
+                    message.content = answer.to_string();
+                }
+            }
+        };
+        let hooks: Vec<Box<dyn ResponseHook>> = vec![
+            Box::new(strip_thinking),
+            Box::new(CountTokens(tokens.clone())),
+        ];
+        run_hooks(&hooks, &mut resp);
+        assert_eq!(resp.data.unwrap().messages[0].content, "你好");
+        assert_eq!(tokens.load(Ordering::Relaxed), 8);
+    }
+}
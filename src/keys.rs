@@ -0,0 +1,217 @@
+use crate::api::RespCode;
+use crate::error::BaichuanError;
+use crate::rate_limit::RateLimiter;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+/// An api key with its secret key, and the rate limit the API applies to it.
+pub(crate) struct KeyPair {
+    pub api_key: String,
+    pub secret_key: String,
+    rate_limiter: Option<RateLimiter>,
+    /// Rejected by the API, so no longer used
+    dropped: AtomicBool,
+}
+
+impl KeyPair {
+    /// The last characters of the api key, enough to tell keys apart in
+    /// logs without revealing them.
+    fn hint(&self) -> String {
+        let chars: Vec<char> = self.api_key.chars().collect();
+        let tail: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+        format!("…{}", tail)
+    }
+
+    fn wait_time(&self) -> Duration {
+        self.rate_limiter
+            .as_ref()
+            .map_or(Duration::ZERO, RateLimiter::wait_time)
+    }
+
+    /// Waits until the key may be used and counts the request against it.
+    pub async fn acquire(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+}
+
+/// The keys of a client, used in turns so that their rate limits add up.
+pub(crate) struct KeyRing {
+    keys: Vec<KeyPair>,
+    next: AtomicUsize,
+    /// How many keys aren't dropped, never brought below one
+    live: AtomicUsize,
+    rpm: Option<u32>,
+}
+
+impl KeyRing {
+    pub fn new(api_key: String, secret_key: String) -> Self {
+        let mut ring = KeyRing {
+            keys: Vec::new(),
+            next: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            rpm: None,
+        };
+        ring.add(api_key, secret_key);
+        ring
+    }
+
+    pub fn add(&mut self, api_key: String, secret_key: String) {
+        self.keys.push(KeyPair {
+            api_key,
+            secret_key,
+            rate_limiter: self.rpm.map(RateLimiter::new),
+            dropped: AtomicBool::new(false),
+        });
+        *self.live.get_mut() += 1;
+    }
+
+    /// Limits every key to `rpm` requests per minute.
+    pub fn set_rate_limit(&mut self, rpm: u32) {
+        self.rpm = Some(rpm);
+        for key in &mut self.keys {
+            key.rate_limiter = Some(RateLimiter::new(rpm));
+        }
+    }
+
+    fn usable(&self) -> impl Iterator<Item = &KeyPair> {
+        self.keys
+            .iter()
+            .filter(|key| !key.dropped.load(Ordering::Relaxed))
+    }
+
+    /// The key for the next request: the first one in turn that may be used
+    /// right away, or else the one free soonest. The last key is never
+    /// dropped, so there always is one.
+    pub fn pick(&self) -> &KeyPair {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let n = self.keys.len();
+        let in_turn = (0..n)
+            .map(|i| &self.keys[(start + i) % n])
+            .filter(|key| !key.dropped.load(Ordering::Relaxed));
+        let mut soonest: Option<(&KeyPair, Duration)> = None;
+        for key in in_turn {
+            let wait = key.wait_time();
+            if wait.is_zero() {
+                return key;
+            }
+            if soonest.is_none_or(|(_, w)| wait < w) {
+                soonest = Some((key, wait));
+            }
+        }
+        soonest.map_or(&self.keys[0], |(key, _)| key)
+    }
+
+    /// How long until some key may be used.
+    pub fn wait_time(&self) -> Duration {
+        self.usable()
+            .map(KeyPair::wait_time)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Takes `key` out of rotation if `err` means the API won't accept it
+    /// anymore and other keys are left. Returns whether it was dropped, in
+    /// which case the request can be sent again with another key.
+    pub fn drop_if_rejected(&self, key: &KeyPair, err: &BaichuanError) -> bool {
+        let rejected = matches!(
+            err.resp_code(),
+            Some(
+                RespCode::InvalidApikey
+                    | RespCode::ApikeyExpired
+                    | RespCode::InvalidSignature
+                    | RespCode::AccountNotFound
+                    | RespCode::AccountLocked
+                    | RespCode::AccountBalanceInsufficient
+                    | RespCode::AccountNotVerified
+            )
+        );
+        if !rejected {
+            return false;
+        }
+        if key.dropped.load(Ordering::Relaxed) {
+            // dropped by a concurrent request already
+            return true;
+        }
+        // counting the key out before marking it, so that concurrent
+        // requests can't drop the last keys between them
+        let counted_out = self
+            .live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+                (live > 1).then(|| live - 1)
+            });
+        if counted_out.is_err() {
+            return false;
+        }
+        if key.dropped.swap(true, Ordering::AcqRel) {
+            self.live.fetch_add(1, Ordering::AcqRel);
+        } else {
+            warn!("api key {} dropped from rotation: {}", key.hint(), err);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expired() -> BaichuanError {
+        BaichuanError::Api {
+            code: RespCode::ApikeyExpired,
+            msg: "Apikey expired".to_string(),
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_round_robin_and_drop() {
+        let mut ring = KeyRing::new("key-a".into(), "secret-a".into());
+        ring.add("key-b".into(), "secret-b".into());
+        assert_eq!(ring.pick().api_key, "key-a");
+        assert_eq!(ring.pick().api_key, "key-b");
+        assert_eq!(ring.pick().api_key, "key-a");
+
+        let key_b = ring.pick();
+        assert!(!ring.drop_if_rejected(key_b, &BaichuanError::Config("x".into())));
+        assert!(ring.drop_if_rejected(key_b, &expired()));
+        assert_eq!(ring.pick().api_key, "key-a");
+        assert_eq!(ring.pick().api_key, "key-a");
+        // The last key is kept, so that its errors reach the caller.
+        assert!(!ring.drop_if_rejected(ring.pick(), &expired()));
+    }
+
+    #[test]
+    fn test_concurrent_drops_keep_a_key() {
+        let mut ring = KeyRing::new("key-0".into(), "secret-0".into());
+        for i in 1..8 {
+            ring.add(format!("key-{}", i), format!("secret-{}", i));
+        }
+        std::thread::scope(|s| {
+            for key in &ring.keys {
+                for _ in 0..4 {
+                    s.spawn(|| ring.drop_if_rejected(key, &expired()));
+                }
+            }
+        });
+        assert_eq!(ring.usable().count(), 1);
+        assert_eq!(ring.live.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_key_is_skipped() {
+        let mut ring = KeyRing::new("key-a".into(), "secret-a".into());
+        ring.add("key-b".into(), "secret-b".into());
+        ring.set_rate_limit(1);
+        ring.pick().acquire().await;
+        assert_eq!(ring.pick().api_key, "key-b");
+        // key-a's turn again, but it is used up for the minute.
+        assert_eq!(ring.pick().api_key, "key-b");
+        ring.keys[1].acquire().await;
+        assert!(!ring.wait_time().is_zero());
+        // Both are used up, and key-a is free first.
+        assert_eq!(ring.pick().api_key, "key-a");
+    }
+}
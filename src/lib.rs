@@ -1,4 +1,67 @@
 mod api;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod client;
+mod embedding;
+mod error;
+mod hook;
+mod keys;
+mod metrics;
+mod mock;
+mod rate_limit;
+mod retry;
+mod session;
+mod stream;
+mod tokens;
+mod transport;
+mod wire;
 
+pub use api::ask;
+pub use api::ask_json;
+pub use api::http_client;
 pub use api::make_baichuan_request;
+pub use api::make_baichuan_request_with_history;
+pub use api::validate_base_url;
+pub use api::validate_credentials;
+pub use api::BaichuanData;
+pub use api::BaichuanResp;
+pub use api::ChatMessage;
+pub use api::HttpOptions;
 pub use api::Model;
+pub use api::Parameters;
+pub use api::RespCode;
+pub use api::ResponseFormat;
+pub use api::SignAlgo;
+pub use api::SignedRequest;
+pub use api::UsageInfo;
+pub use api::DEFAULT_BASE_URL;
+pub use api::DEFAULT_USER_AGENT;
+pub use api::USAGE_UNAVAILABLE;
+pub use builder::RequestBuilder;
+pub use client::BaichuanClient;
+pub use embedding::make_embedding_request;
+pub use embedding::EMBEDDING_MODEL;
+pub use embedding::MAX_EMBEDDING_INPUTS;
+pub use error::BaichuanError;
+pub use hook::ResponseHook;
+pub use metrics::RequestMetrics;
+pub use metrics::DEFAULT_SLOW_REQUEST_THRESHOLD;
+pub use mock::MockTransport;
+pub use mock::MOCK_ANSWER_PREFIX;
+pub use rate_limit::RateLimiter;
+pub use retry::make_baichuan_request_retrying;
+pub use retry::retry_with_backoff;
+pub use retry::RetryPolicy;
+pub use session::load_session;
+pub use session::save_session;
+pub use stream::make_baichuan_stream_request;
+pub use stream::make_baichuan_stream_request_with_history;
+pub use stream::StreamEvent;
+pub use tokens::estimate_conversation_tokens;
+pub use tokens::estimate_tokens;
+pub use transport::Transport;
+pub use transport::TransportResponse;
+pub use wire::format_headers;
+pub use wire::set_wire_secrets;
+pub use wire::WIRE_TARGET;
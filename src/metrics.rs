@@ -0,0 +1,58 @@
+use std::fmt;
+use std::time::Duration;
+use tracing::warn;
+
+/// Requests taking longer than this are warned about, unless configured
+/// otherwise.
+pub const DEFAULT_SLOW_REQUEST_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Sizes and timing of one completed request, e.g. to find out whether long
+/// conversations slow down the answers.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct RequestMetrics {
+    /// Size of the serialized request body.
+    pub request_bytes: usize,
+    /// Size of the response body.
+    pub response_bytes: usize,
+    /// From sending the request until the whole response was read.
+    pub latency: Duration,
+}
+
+impl RequestMetrics {
+    /// Warns if the request took longer than `threshold`.
+    pub(crate) fn warn_if_slow(&self, threshold: Option<Duration>) {
+        if threshold.is_some_and(|threshold| self.latency > threshold) {
+            warn!("slow request: {}", self);
+        }
+    }
+}
+
+impl fmt::Display for RequestMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}ms, sent {} bytes, received {} bytes",
+            self.latency.as_millis(),
+            self.request_bytes,
+            self.response_bytes
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metrics_display() {
+        let metrics = RequestMetrics {
+            request_bytes: 120,
+            response_bytes: 2048,
+            latency: Duration::from_millis(1500),
+        };
+        assert_eq!(
+            metrics.to_string(),
+            "1500ms, sent 120 bytes, received 2048 bytes"
+        );
+    }
+}
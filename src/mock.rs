@@ -0,0 +1,160 @@
+use crate::api::{BaichuanData, BaichuanResp, ChatMessage, RespCode, UsageInfo, CHAT_PATH};
+use crate::error::BaichuanError;
+use crate::stream::STREAM_PATH;
+use crate::tokens::{estimate_conversation_tokens, estimate_tokens};
+use crate::transport::{Transport, TransportResponse};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Marks every answer of a [`MockTransport`], so that it can't be taken for
+/// one of the model's.
+pub const MOCK_ANSWER_PREFIX: &str = "[mock] ";
+
+/// Characters per streamed piece of a mocked answer.
+const STREAM_PIECE_CHARS: usize = 4;
+
+/// A [`Transport`] answering chat requests locally, for demos and tests
+/// without network access or credentials. The answer echoes the last user
+/// message after [`MOCK_ANSWER_PREFIX`], with the usage estimated by
+/// [`estimate_tokens`]. Other requests, such as embeddings, are rejected.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MockTransport;
+
+#[derive(Deserialize)]
+struct MockReq {
+    messages: Vec<ChatMessage>,
+}
+
+impl MockTransport {
+    fn answer(body: &[u8]) -> Result<BaichuanResp, BaichuanError> {
+        let request: MockReq =
+            serde_json::from_slice(body).map_err(|e| BaichuanError::parse(e, body))?;
+        let prompt = request
+            .messages
+            .iter()
+            .rfind(|m| m.role == "user")
+            .map_or("", |m| m.content.as_str());
+        let answer = format!("{}{}", MOCK_ANSWER_PREFIX, prompt);
+        let prompt_tokens = estimate_conversation_tokens(&request.messages) as i64;
+        let answer_tokens = estimate_tokens(&answer) as i64;
+        let mut message = ChatMessage::assistant(answer);
+        message.finish_reason = Some("stop".into());
+        Ok(BaichuanResp {
+            code: RespCode::Success,
+            msg: "success".to_string(),
+            data: Some(BaichuanData {
+                messages: vec![message],
+            }),
+            usage: Some(UsageInfo {
+                prompt_tokens,
+                answer_tokens,
+                total_tokens: prompt_tokens + answer_tokens,
+            }),
+        })
+    }
+
+    /// `resp` as the events of the stream endpoint, a few characters each.
+    fn events(resp: BaichuanResp) -> Result<Vec<u8>, BaichuanError> {
+        let message = resp.data.and_then(|d| d.messages.into_iter().next());
+        let message = message.unwrap_or_else(|| ChatMessage::assistant(""));
+        let chars: Vec<char> = message.content.chars().collect();
+        let pieces: Vec<String> = chars
+            .chunks(STREAM_PIECE_CHARS)
+            .map(|piece| piece.iter().collect())
+            .collect();
+        let mut body = String::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            let last = i + 1 == pieces.len();
+            let mut event = ChatMessage::assistant(piece.as_str());
+            let mut usage = None;
+            if last {
+                event.finish_reason = message.finish_reason.clone();
+                usage = resp.usage.clone();
+            }
+            let data = BaichuanResp {
+                code: RespCode::Success,
+                msg: "success".to_string(),
+                data: Some(BaichuanData {
+                    messages: vec![event],
+                }),
+                usage,
+            };
+            let data = serde_json::to_string(&data).map_err(BaichuanError::Serialization)?;
+            body.push_str(&format!("data: {}\n\n", data));
+        }
+        body.push_str("data: [DONE]\n\n");
+        Ok(body.into_bytes())
+    }
+}
+
+impl Transport for MockTransport {
+    async fn post_json(
+        &self,
+        url: &str,
+        _headers: HeaderMap,
+        body: Vec<u8>,
+        _timeout: Option<Duration>,
+    ) -> Result<TransportResponse, BaichuanError> {
+        let resp = Self::answer(&body);
+        let (content_type, body) = if url.ends_with(STREAM_PATH) {
+            ("text/event-stream", Self::events(resp?)?)
+        } else if url.ends_with(CHAT_PATH) {
+            let body = serde_json::to_vec(&resp?).map_err(BaichuanError::Serialization)?;
+            ("application/json", body)
+        } else {
+            return Err(BaichuanError::Config(format!(
+                "the mock only answers chat requests, not {}",
+                url
+            )));
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        Ok(TransportResponse { headers, body })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{endpoint, sign_request, Model, SignAlgo, DEFAULT_BASE_URL};
+    use crate::builder::RequestBuilder;
+    use crate::stream::{send_buffered_stream_request, StreamEvent};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_mock_echoes_prompt() -> Result<(), BaichuanError> {
+        let resp = RequestBuilder::new(&MockTransport, "api_key", "secret_key")
+            .messages(vec![ChatMessage::user("你好")])
+            .send()
+            .await?;
+        assert_eq!(resp.answer(), "[mock] 你好");
+        assert!(resp.usage.is_some_and(|u| u.total_tokens > 0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream() -> Result<(), BaichuanError> {
+        let request = serde_json::json!({
+            "model": Model::Baichuan2Turbo,
+            "messages": [ChatMessage::user("hello world")],
+        });
+        let url = endpoint(DEFAULT_BASE_URL, STREAM_PATH);
+        let signed = sign_request(url, "api_key", "secret_key", SignAlgo::Md5, &request, None)?;
+        let events: Vec<_> = send_buffered_stream_request(&MockTransport, signed)
+            .await?
+            .collect()
+            .await;
+        let mut answer = String::new();
+        let mut usage = None;
+        for event in events {
+            match event? {
+                StreamEvent::Message(m) => answer.push_str(&m.content),
+                StreamEvent::Usage(u) => usage = Some(u),
+            }
+        }
+        assert_eq!(answer, "[mock] hello world");
+        assert!(usage.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Client-side limit of `rpm` requests in any 60 second window, matching the
+/// per-apikey limit the API enforces with
+/// [`RespCode::AccountRequestTooFrequent`](crate::RespCode::AccountRequestTooFrequent).
+#[derive(Debug)]
+pub struct RateLimiter {
+    rpm: usize,
+    /// When the requests of the current window were sent, oldest first.
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    /// `rpm` must be at least 1.
+    pub fn new(rpm: u32) -> Self {
+        RateLimiter {
+            rpm: rpm.max(1) as usize,
+            sent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// How long until another request may be sent, zero if right away.
+    pub fn wait_time(&self) -> Duration {
+        let mut sent = self.sent.lock().unwrap();
+        self.wait_locked(&mut sent, Instant::now())
+    }
+
+    fn wait_locked(&self, sent: &mut VecDeque<Instant>, now: Instant) -> Duration {
+        while sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= WINDOW)
+        {
+            sent.pop_front();
+        }
+        if sent.len() < self.rpm {
+            return Duration::ZERO;
+        }
+        (sent[0] + WINDOW).saturating_duration_since(now)
+    }
+
+    /// Waits until a request may be sent and counts it against the limit.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().unwrap();
+                let now = Instant::now();
+                let wait = self.wait_locked(&mut sent, now);
+                if wait.is_zero() {
+                    sent.push_back(now);
+                    return;
+                }
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_requests_per_window() {
+        let limiter = RateLimiter::new(2);
+        limiter.acquire().await;
+        assert_eq!(limiter.wait_time(), Duration::ZERO);
+        limiter.acquire().await;
+        let wait = limiter.wait_time();
+        assert!(wait > Duration::from_secs(59) && wait <= WINDOW);
+    }
+
+    #[test]
+    fn test_window_expires() {
+        let limiter = RateLimiter::new(1);
+        let start = Instant::now();
+        let mut sent = VecDeque::from([start]);
+        assert!(!limiter.wait_locked(&mut sent, start).is_zero());
+        assert!(limiter.wait_locked(&mut sent, start + WINDOW).is_zero());
+        assert!(sent.is_empty());
+    }
+}
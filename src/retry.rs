@@ -0,0 +1,249 @@
+use crate::api::{new_request_id, BaichuanResp, ChatMessage, Model, Parameters, RespCode};
+use crate::builder::RequestBuilder;
+use crate::error::BaichuanError;
+use crate::transport::Transport;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// How often and how patiently a rate-limited request is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, so `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub base_delay: Duration,
+    /// Like `base_delay`, for requests rejected because the account is
+    /// temporarily locked, which takes longer to clear than a rate limit.
+    pub lock_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(2),
+            lock_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential delay for the given retry (starting at 0), plus up to one
+    /// `base_delay` of random jitter so parallel clients don't retry in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        backoff(self.base_delay, retry)
+    }
+
+    /// [`delay`](Self::delay) for a retry after `err`.
+    fn delay_after(&self, err: &BaichuanError, retry: u32) -> Duration {
+        if err.resp_code() == Some(RespCode::AccountTempLocked) {
+            backoff(self.lock_delay, retry)
+        } else {
+            self.delay(retry)
+        }
+    }
+}
+
+fn backoff(base: Duration, retry: u32) -> Duration {
+    let backoff = base.saturating_mul(2u32.saturating_pow(retry));
+    let jitter_ms = base.as_millis() as u64;
+    let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms));
+    backoff + jitter
+}
+
+/// Runs `f` until it succeeds, fails with a non-retryable error, or the
+/// policy's retries are used up.
+pub async fn retry_with_backoff<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<T, BaichuanError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, BaichuanError>>,
+{
+    let mut retry = 0;
+    loop {
+        match f().await {
+            Err(e) if e.is_retryable() && retry < policy.max_retries => {
+                let delay = policy.delay_after(&e, retry);
+                if e.resp_code() == Some(RespCode::AccountTempLocked) {
+                    warn!(
+                        "the account is temporarily locked, retrying in {:.0}s",
+                        delay.as_secs_f32()
+                    );
+                } else {
+                    warn!("{}, retrying in {:.1}s", e, delay.as_secs_f32());
+                }
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            r => return r,
+        }
+    }
+}
+
+/// [`crate::make_baichuan_request_with_history`] that backs off and retries
+/// when the API is rate limiting us. Every attempt is sent with the same
+/// `X-BC-Request-Id`.
+pub async fn make_baichuan_request_retrying(
+    client: &impl Transport,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<ChatMessage>,
+    parameters: &Parameters,
+    policy: &RetryPolicy,
+) -> Result<BaichuanResp, BaichuanError> {
+    let request_id = new_request_id();
+    retry_with_backoff(policy, || {
+        RequestBuilder::new(client, api_key, secret_key)
+            .model(model)
+            .messages(messages.clone())
+            .parameters(parameters.clone())
+            .request_id(&request_id)
+            .send()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::TransportResponse;
+    use reqwest::header::HeaderMap;
+    use std::cell::Cell;
+    use std::sync::Mutex;
+
+    fn api_error(code: RespCode) -> BaichuanError {
+        BaichuanError::Api {
+            code,
+            msg: String::new(),
+            request_id: None,
+        }
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            lock_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_delay_grows() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            lock_delay: Duration::from_millis(1000),
+        };
+        let delay = policy.delay(2);
+        assert!(delay >= Duration::from_millis(400));
+        assert!(delay <= Duration::from_millis(500));
+        let delay = policy.delay_after(&api_error(RespCode::AccountTempLocked), 0);
+        assert!(delay >= Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limit() {
+        let attempts = Cell::new(0);
+        let r = retry_with_backoff(&fast_policy(), || async {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(api_error(RespCode::AccountRequestTooFrequent))
+        })
+        .await;
+        assert!(r.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fails_fast_on_bad_key() {
+        let attempts = Cell::new(0);
+        let r = retry_with_backoff(&fast_policy(), || async {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(api_error(RespCode::InvalidApikey))
+        })
+        .await;
+        assert_eq!(r.unwrap_err().resp_code(), Some(RespCode::InvalidApikey));
+        assert_eq!(attempts.get(), 1);
+    }
+
+    /// Rejects the first request with `code` and records the request ids it
+    /// was sent.
+    struct FailsOnce {
+        code: RespCode,
+        request_ids: Mutex<Vec<String>>,
+    }
+
+    impl FailsOnce {
+        fn new(code: RespCode) -> Self {
+            FailsOnce {
+                code,
+                request_ids: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Transport for FailsOnce {
+        async fn post_json(
+            &self,
+            _url: &str,
+            headers: HeaderMap,
+            _body: Vec<u8>,
+            _timeout: Option<Duration>,
+        ) -> Result<TransportResponse, BaichuanError> {
+            let mut request_ids = self.request_ids.lock().unwrap();
+            request_ids.push(headers["X-BC-Request-Id"].to_str().unwrap().to_string());
+            let body = if request_ids.len() == 1 {
+                format!(r#"{{"code":{},"msg":"rejected"}}"#, self.code as i32)
+            } else {
+                r#"{"code":0,"msg":"success"}"#.to_string()
+            };
+            Ok(TransportResponse {
+                headers: HeaderMap::new(),
+                body: body.into_bytes(),
+            })
+        }
+    }
+
+    async fn send(transport: &FailsOnce) -> Result<BaichuanResp, BaichuanError> {
+        make_baichuan_request_retrying(
+            transport,
+            "api_key",
+            "secret_key",
+            Model::Baichuan2_53B,
+            vec![ChatMessage::user("你好")],
+            &Parameters::default(),
+            &fast_policy(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_retry_reuses_request_id() -> Result<(), BaichuanError> {
+        let transport = FailsOnce::new(RespCode::AccountRequestTooFrequent);
+        send(&transport).await?;
+        let request_ids = transport.request_ids.lock().unwrap();
+        assert_eq!(request_ids.len(), 2);
+        assert_eq!(request_ids[0], request_ids[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_temporary_lock_is_retried() -> Result<(), BaichuanError> {
+        let transport = FailsOnce::new(RespCode::AccountTempLocked);
+        send(&transport).await?;
+        assert_eq!(transport.request_ids.lock().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_permanent_lock_fails_fast() {
+        let transport = FailsOnce::new(RespCode::AccountLocked);
+        let err = send(&transport).await.unwrap_err();
+        assert_eq!(err.resp_code(), Some(RespCode::AccountLocked));
+        assert_eq!(transport.request_ids.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::api::ChatMessage;
+use crate::error::BaichuanError;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Loads a conversation saved by [`save_session`], or `None` if there is no
+/// file at `path` yet.
+pub fn load_session(path: impl AsRef<Path>) -> Result<Option<Vec<ChatMessage>>, BaichuanError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(BaichuanError::Io(e)),
+    };
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| BaichuanError::parse(e, text.as_bytes()))
+}
+
+/// Saves the conversation as a JSON array of messages.
+pub fn save_session(path: impl AsRef<Path>, messages: &[ChatMessage]) -> Result<(), BaichuanError> {
+    let text = serde_json::to_string_pretty(messages).map_err(BaichuanError::Serialization)?;
+    fs::write(path, text).map_err(BaichuanError::Io)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() -> Result<(), BaichuanError> {
+        let path = std::env::temp_dir().join(format!("bc_cli_session_{}.json", ulid::Ulid::new()));
+        assert!(load_session(&path)?.is_none());
+        let messages = vec![ChatMessage::user("你好"), ChatMessage::assistant("你好！")];
+        save_session(&path, &messages)?;
+        let loaded = load_session(&path)?.expect("session should exist");
+        fs::remove_file(&path).map_err(BaichuanError::Io)?;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].content, "你好！");
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use crate::api::{
+    build_request, check_resp, endpoint, parse_resp, post_request, response_request_id,
+    user_messages, warn_on_clock_skew, BaichuanReq, BaichuanResp, ChatMessage, Model, Parameters,
+    SignAlgo, SignedRequest, UsageInfo, DEFAULT_BASE_URL,
+};
+use crate::error::BaichuanError;
+use crate::transport::{Transport, TransportResponse};
+use crate::wire;
+use futures::future::Either;
+use futures::{stream, Stream, StreamExt};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use std::collections::VecDeque;
+
+pub(crate) const STREAM_PATH: &str = "/v1/stream/chat";
+
+/// Sentinel payload the server sends as the last `data:` event.
+const DONE: &[u8] = b"[DONE]";
+
+/// An item of a streamed answer.
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// The next piece of the answer.
+    Message(ChatMessage),
+    /// Token usage, usually sent along with the last piece.
+    Usage(UsageInfo),
+}
+
+fn resp_events(resp: BaichuanResp) -> impl Iterator<Item = Result<StreamEvent, BaichuanError>> {
+    let messages = resp.data.map(|d| d.messages).unwrap_or_default();
+    messages
+        .into_iter()
+        .map(StreamEvent::Message)
+        .chain(resp.usage.map(StreamEvent::Usage))
+        .map(Ok)
+}
+
+/// Incremental decoder for the server-sent events returned by the stream
+/// endpoint. Bytes are buffered until a full line is available, so multi-byte
+/// UTF-8 characters split across network chunks are never decoded in halves.
+#[derive(Default)]
+struct SseDecoder {
+    buf: Vec<u8>,
+    done: bool,
+    /// Attached to errors reported within the stream.
+    request_id: Option<String>,
+}
+
+impl SseDecoder {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Result<StreamEvent, BaichuanError>> {
+        self.buf.extend_from_slice(chunk);
+        let mut messages = vec![];
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            self.decode_line(&line, &mut messages);
+        }
+        messages
+    }
+
+    /// Decodes whatever is left in the buffer once the body has ended.
+    fn finish(&mut self) -> Vec<Result<StreamEvent, BaichuanError>> {
+        let line = std::mem::take(&mut self.buf);
+        let mut messages = vec![];
+        self.decode_line(&line, &mut messages);
+        messages
+    }
+
+    fn decode_line(&mut self, line: &[u8], out: &mut Vec<Result<StreamEvent, BaichuanError>>) {
+        if self.done {
+            return;
+        }
+        if !line.trim_ascii().is_empty() {
+            wire::log_body(line.trim_ascii_end());
+        }
+        // only `data:` fields carry payloads, everything else (comments,
+        // `event:`/`id:` fields and blank separators) is ignored
+        let Some(data) = line.strip_prefix(b"data:") else {
+            return;
+        };
+        let data = data.trim_ascii();
+        if data == DONE {
+            self.done = true;
+            return;
+        }
+        let resp = serde_json::from_slice::<BaichuanResp>(data)
+            .map_err(|e| BaichuanError::parse(e, data))
+            .and_then(|resp| check_resp(resp, self.request_id.as_deref()));
+        match resp {
+            Ok(resp) => out.extend(resp_events(resp)),
+            Err(e) => out.push(Err(e)),
+        }
+    }
+}
+
+struct StreamState<S> {
+    bytes: std::pin::Pin<Box<S>>,
+    decoder: SseDecoder,
+    pending: VecDeque<Result<StreamEvent, BaichuanError>>,
+    finished: bool,
+}
+
+fn decode_stream<S, B, E>(
+    bytes: S,
+    request_id: Option<String>,
+) -> impl Stream<Item = Result<StreamEvent, BaichuanError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<BaichuanError>,
+{
+    let state = StreamState {
+        bytes: Box::pin(bytes),
+        decoder: SseDecoder {
+            request_id,
+            ..SseDecoder::default()
+        },
+        pending: VecDeque::new(),
+        finished: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(message) = state.pending.pop_front() {
+                return Some((message, state));
+            }
+            if state.finished || state.decoder.done {
+                return None;
+            }
+            match state.bytes.next().await {
+                Some(Ok(chunk)) => state.pending.extend(state.decoder.feed(chunk.as_ref())),
+                Some(Err(e)) => {
+                    state.finished = true;
+                    state.pending.push_back(Err(e.into()));
+                }
+                None => {
+                    state.finished = true;
+                    state.pending.extend(state.decoder.finish());
+                }
+            }
+        }
+    })
+}
+
+/// Like [`crate::make_baichuan_request`], but yields the partial assistant
+/// messages as the server generates them, followed by the token usage.
+pub async fn make_baichuan_stream_request(
+    client: &reqwest::Client,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<String>,
+    parameters: &Parameters,
+) -> Result<impl Stream<Item = Result<StreamEvent, BaichuanError>>, BaichuanError> {
+    make_baichuan_stream_request_with_history(
+        client,
+        api_key,
+        secret_key,
+        model,
+        user_messages(messages),
+        parameters,
+    )
+    .await
+}
+
+/// Streaming counterpart of [`crate::make_baichuan_request_with_history`].
+pub async fn make_baichuan_stream_request_with_history(
+    client: &reqwest::Client,
+    api_key: &str,
+    secret_key: &str,
+    model: Model,
+    messages: Vec<ChatMessage>,
+    parameters: &Parameters,
+) -> Result<impl Stream<Item = Result<StreamEvent, BaichuanError>>, BaichuanError> {
+    let request = build_request(model, messages, parameters)?;
+    send_stream_request(
+        client,
+        DEFAULT_BASE_URL,
+        api_key,
+        secret_key,
+        SignAlgo::Md5,
+        &request,
+        None,
+    )
+    .await
+}
+
+/// Sends a built request to the stream endpoint.
+pub(crate) async fn send_stream_request(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    secret_key: &str,
+    sign_algo: SignAlgo,
+    request: &BaichuanReq,
+    request_id: Option<&str>,
+) -> Result<impl Stream<Item = Result<StreamEvent, BaichuanError>>, BaichuanError> {
+    let url = endpoint(base_url, STREAM_PATH);
+    let (response, req_id) = post_request(
+        client, url, api_key, secret_key, sign_algo, request, request_id,
+    )
+    .await?;
+    if is_json(response.headers()) {
+        // errors such as rate limiting come back as a plain JSON body rather
+        // than as an event stream
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        wire::log_body(&body);
+        let resp: BaichuanResp =
+            serde_json::from_slice(&body).map_err(|e| BaichuanError::parse(e, &body))?;
+        let resp =
+            check_resp(resp, Some(&req_id)).inspect_err(|e| warn_on_clock_skew(e, &headers))?;
+        return Ok(Either::Left(stream::iter(resp_events(resp))));
+    }
+    Ok(Either::Right(decode_stream(
+        response.bytes_stream(),
+        Some(req_id),
+    )))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Like [`send_stream_request`], over a [`Transport`] such as
+/// [`crate::MockTransport`]. The transport returns the whole body at once,
+/// so the events only follow once the answer is complete.
+pub(crate) async fn send_buffered_stream_request(
+    transport: &impl Transport,
+    signed: SignedRequest,
+) -> Result<impl Stream<Item = Result<StreamEvent, BaichuanError>>, BaichuanError> {
+    let TransportResponse { headers, body } = transport
+        .post_json(&signed.url, signed.headers, signed.body.into_bytes(), None)
+        .await?;
+    let req_id = response_request_id(&headers, signed.request_id);
+    if is_json(&headers) {
+        let resp = parse_resp(&body, Some(&req_id))?;
+        return Ok(Either::Left(stream::iter(resp_events(resp))));
+    }
+    let body = stream::iter([Ok::<_, BaichuanError>(body)]);
+    Ok(Either::Right(decode_stream(body, Some(req_id))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::RespCode;
+
+    fn contents(events: Vec<Result<StreamEvent, BaichuanError>>) -> Vec<String> {
+        events
+            .into_iter()
+            .filter_map(|e| match e.expect("should decode") {
+                StreamEvent::Message(m) => Some(m.content),
+                StreamEvent::Usage(_) => None,
+            })
+            .collect()
+    }
+
+    fn chunk(content: &str) -> String {
+        format!(
+            "data: {{\"code\":0,\"msg\":\"success\",\"data\":{{\"messages\":[{{\"role\":\"assistant\",\"content\":\"{}\"}}]}}}}\n\n",
+            content
+        )
+    }
+
+    async fn collect(chunks: Vec<Vec<u8>>) -> Vec<Result<StreamEvent, BaichuanError>> {
+        let bytes = stream::iter(chunks.into_iter().map(Ok::<_, reqwest::Error>));
+        decode_stream(bytes, Some("req-1".to_string()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream() {
+        let body = format!("{}{}data: [DONE]\n\n", chunk("你好"), chunk("！"));
+        let events = collect(vec![body.into_bytes()]).await;
+        assert_eq!(contents(events), vec!["你好", "！"]);
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_usage() {
+        let body = concat!(
+            "data: {\"code\":0,\"msg\":\"success\",\"data\":{\"messages\":[{\"role\":\"assistant\",\"content\":\"好\",\"finish_reason\":\"stop\"}]},",
+            "\"usage\":{\"prompt_tokens\":3,\"answer_tokens\":1,\"total_tokens\":4}}\n\n",
+        );
+        let events = collect(vec![body.as_bytes().to_vec()]).await;
+        assert_eq!(events.len(), 2);
+        match &events[1] {
+            Ok(StreamEvent::Usage(usage)) => assert_eq!(usage.total_tokens, 4),
+            other => panic!("expected usage, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_split_utf8() {
+        let body = chunk("你好").into_bytes();
+        // split in the middle of the three-byte encoding of 你
+        let split = body.iter().position(|b| *b == 0xe4).unwrap() + 1;
+        let events = collect(vec![body[..split].to_vec(), body[split..].to_vec()]).await;
+        assert_eq!(contents(events), vec!["你好"]);
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_stops_at_done() {
+        let body = format!("data: [DONE]\n\n{}", chunk("ignored"));
+        assert!(collect(vec![body.into_bytes()]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_api_error() {
+        let body = "data: {\"code\":10203,\"msg\":\"Request too frequent\"}\n\n";
+        let events = collect(vec![body.as_bytes().to_vec()]).await;
+        assert_eq!(events.len(), 1);
+        let err = events[0].as_ref().expect_err("should be rejected");
+        assert_eq!(err.resp_code(), Some(RespCode::AccountRequestTooFrequent));
+        assert_eq!(err.request_id(), Some("req-1"));
+    }
+}
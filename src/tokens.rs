@@ -0,0 +1,102 @@
+//! Rough token and cost estimates for a prompt before it is sent. They are
+//! heuristics, the exact figures are only known from the [`crate::UsageInfo`]
+//! of the response.
+
+use crate::api::{ChatMessage, Model};
+
+/// Tokens the API adds around each message for its role and separators.
+const MESSAGE_OVERHEAD: usize = 4;
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2E80}'..='\u{9FFF}'     // radicals, kana, CJK symbols and ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // hangul
+        | '\u{F900}'..='\u{FAFF}'   // compatibility ideographs
+        | '\u{FF00}'..='\u{FFEF}'   // full width forms
+        | '\u{20000}'..='\u{2FA1F}' // extension ideographs
+    )
+}
+
+/// Estimates the tokens `text` takes: about one per CJK character and one
+/// per four characters of any other word, with every word or symbol costing
+/// at least one.
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut word: usize = 0;
+    for c in text.chars() {
+        if c.is_whitespace() || is_cjk(c) {
+            tokens += word.div_ceil(4);
+            word = 0;
+            if !c.is_whitespace() {
+                tokens += 1;
+            }
+        } else {
+            word += 1;
+        }
+    }
+    tokens + word.div_ceil(4)
+}
+
+/// Estimates the prompt tokens of a whole conversation.
+pub fn estimate_conversation_tokens(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .map(|m| estimate_tokens(&m.content) + MESSAGE_OVERHEAD)
+        .sum()
+}
+
+impl Model {
+    /// The most tokens of prompt and answer together the model accepts.
+    pub fn context_window(&self) -> usize {
+        match self {
+            Model::Baichuan2_53B => 4096,
+            Model::Baichuan2_13B => 4096,
+            Model::Baichuan2Turbo => 32768,
+            Model::Baichuan2Turbo192k => 196608,
+        }
+    }
+
+    /// List price in CNY per 1000 tokens, as published by Baichuan at the
+    /// time of writing.
+    pub fn price_per_1k_tokens(&self) -> f64 {
+        match self {
+            Model::Baichuan2_53B => 0.02,
+            Model::Baichuan2_13B => 0.01,
+            Model::Baichuan2Turbo => 0.008,
+            Model::Baichuan2Turbo192k => 0.016,
+        }
+    }
+
+    /// Estimated price in CNY of `tokens` tokens.
+    pub fn estimate_cost(&self, tokens: usize) -> f64 {
+        tokens as f64 / 1000.0 * self.price_per_1k_tokens()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("你好，世界"), 5);
+        assert_eq!(estimate_tokens("hello world"), 4);
+        assert_eq!(estimate_tokens("用 Rust 写"), 3);
+    }
+
+    #[test]
+    fn test_estimate_conversation_tokens() {
+        let messages = vec![ChatMessage::user("你好"), ChatMessage::assistant("你好！")];
+        assert_eq!(
+            estimate_conversation_tokens(&messages),
+            2 + 3 + 2 * MESSAGE_OVERHEAD
+        );
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let cost = Model::Baichuan2Turbo.estimate_cost(2000);
+        assert!((cost - 0.016).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::error::BaichuanError;
+use crate::wire;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+
+/// A `200 OK` answer to [`Transport::post_json`].
+pub struct TransportResponse {
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// The HTTP layer under the non-streaming requests, so that response
+/// handling can be tested with canned bodies instead of the network.
+pub trait Transport {
+    /// POSTs the JSON `body` with `headers` to `url` and returns the response,
+    /// or [`BaichuanError::Http`] or [`BaichuanError::Gateway`] if the server
+    /// didn't answer `200 OK`.
+    /// `timeout` overrides the transport's own timeout for this request.
+    fn post_json(
+        &self,
+        url: &str,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<TransportResponse, BaichuanError>> + Send;
+}
+
+impl Transport for reqwest::Client {
+    async fn post_json(
+        &self,
+        url: &str,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> Result<TransportResponse, BaichuanError> {
+        wire::log_request(url, &headers, &body);
+        let mut request = self.post(url).headers(headers).body(body);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        wire::log_response(status, &headers);
+        let body = response.bytes().await?.to_vec();
+        wire::log_body(&body);
+        if status != StatusCode::OK {
+            return Err(BaichuanError::status(status, &headers, &body));
+        }
+        Ok(TransportResponse { headers, body })
+    }
+}
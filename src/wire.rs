@@ -0,0 +1,101 @@
+//! Logs of the exact requests and responses exchanged with the API, emitted
+//! at `TRACE` level under the [`WIRE_TARGET`] target so they can be enabled
+//! independently of the other logs.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::trace;
+
+pub const WIRE_TARGET: &str = "baichuan_cli::wire";
+
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Whether the wire logs include the api key, which is redacted by default.
+pub fn set_wire_secrets(show: bool) {
+    SHOW_SECRETS.store(show, Ordering::Relaxed);
+}
+
+/// Formats headers one per line, sorted by name, with the api key redacted
+/// unless `show_secrets`. The signature is kept, so that a printed request
+/// can be checked or sent as it is.
+pub fn format_headers(headers: &HeaderMap, show_secrets: bool) -> String {
+    format_redacted(headers, show_secrets, false)
+}
+
+/// Like [`format_headers`], redacting the signature too if `signature`.
+fn format_redacted(headers: &HeaderMap, show_secrets: bool, signature: bool) -> String {
+    let mut lines: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| {
+            let value = header_value(name, value, show_secrets, signature);
+            format!("{}: {}", name, value)
+        })
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+/// The value of a header to show, `********` in place of the api key, and
+/// of the signature if `signature`, unless `show_secrets`.
+pub(crate) fn header_value<'a>(
+    name: &HeaderName,
+    value: &'a HeaderValue,
+    show_secrets: bool,
+    signature: bool,
+) -> &'a str {
+    if !show_secrets && name == AUTHORIZATION {
+        "Bearer ********"
+    } else if !show_secrets && signature && name == "X-BC-Signature" {
+        "********"
+    } else {
+        value.to_str().unwrap_or("<binary>")
+    }
+}
+
+pub(crate) fn log_request(url: &str, headers: &HeaderMap, body: &[u8]) {
+    trace!(
+        target: WIRE_TARGET,
+        "POST {}\n{}\n\n{}",
+        url,
+        format_redacted(headers, SHOW_SECRETS.load(Ordering::Relaxed), true),
+        String::from_utf8_lossy(body)
+    );
+}
+
+pub(crate) fn log_response(status: StatusCode, headers: &HeaderMap) {
+    trace!(
+        target: WIRE_TARGET,
+        "{}\n{}",
+        status,
+        format_headers(headers, true)
+    );
+}
+
+/// A response body or, for streams, one line of it.
+pub(crate) fn log_body(body: &[u8]) {
+    trace!(target: WIRE_TARGET, "{}", String::from_utf8_lossy(body));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_headers_redacts_key() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer sk-123".parse().unwrap());
+        headers.insert("X-BC-Timestamp", "1700000000".parse().unwrap());
+        headers.insert("X-BC-Signature", "0123abcd".parse().unwrap());
+        assert_eq!(
+            format_headers(&headers, false),
+            "authorization: Bearer ********\nx-bc-signature: 0123abcd\nx-bc-timestamp: 1700000000"
+        );
+        assert_eq!(
+            format_redacted(&headers, false, true),
+            "authorization: Bearer ********\nx-bc-signature: ********\nx-bc-timestamp: 1700000000"
+        );
+        let shown = format_redacted(&headers, true, true);
+        assert!(shown.contains("sk-123") && shown.contains("0123abcd"));
+    }
+}